@@ -0,0 +1,135 @@
+use crate::mpris::{PlayerInfo, PlayerStatus};
+use crate::player_filter::{player_priority, PlayerPattern};
+use std::cmp::Reverse;
+use std::time::Instant;
+
+/// A player known to [ActivePlayers], along with its playback state.
+#[derive(Debug, Clone)]
+pub struct TrackedPlayer {
+    pub sender: String,
+    pub info: PlayerInfo,
+    pub status: Option<PlayerStatus>,
+
+    // When the player last transitioned to `Playing`.
+    last_playing: Option<Instant>,
+}
+
+/// Keeps the players ranked by which one is "the" current player: playing
+/// players rank before paused or stopped ones, then players matching an
+/// earlier `player_priority` pattern, then players which started playing
+/// more recently.
+pub struct ActivePlayers {
+    priority: Vec<PlayerPattern>,
+    players: Vec<TrackedPlayer>,
+}
+
+impl ActivePlayers {
+    pub fn new(priority: &[PlayerPattern]) -> Self {
+        Self {
+            priority: priority.to_vec(),
+            players: Vec::new(),
+        }
+    }
+
+    /// Records a player's playback status.
+    pub fn handle_status(&mut self, sender: &str, info: &PlayerInfo, status: PlayerStatus) {
+        let index = match self.players.iter().position(|p| p.sender == sender) {
+            Some(index) => index,
+            None => {
+                self.players.push(TrackedPlayer {
+                    sender: sender.to_string(),
+                    info: info.clone(),
+                    status: None,
+                    last_playing: None,
+                });
+                self.players.len() - 1
+            }
+        };
+
+        let player = &mut self.players[index];
+        if status == PlayerStatus::Playing && player.status != Some(PlayerStatus::Playing) {
+            player.last_playing = Some(Instant::now());
+        }
+        player.info = info.clone();
+        player.status = Some(status);
+        self.rank();
+    }
+
+    /// Records the well-known name a player acquired after it was first seen.
+    pub fn set_bus_name(&mut self, sender: &str, bus_name: &str) {
+        if let Some(player) = self.players.iter_mut().find(|p| p.sender == sender) {
+            player.info.bus_name = Some(bus_name.to_string());
+            self.rank();
+        }
+    }
+
+    /// Forgets a player which has exited.
+    pub fn remove(&mut self, sender: &str) {
+        self.players.retain(|p| p.sender != sender);
+    }
+
+    /// The current player, if any player is known.
+    pub fn active(&self) -> Option<&TrackedPlayer> {
+        self.players.first()
+    }
+
+    fn rank(&mut self) {
+        let priority = &self.priority;
+        self.players.sort_by_cached_key(|p| {
+            (
+                p.status != Some(PlayerStatus::Playing),
+                player_priority(&p.info, priority),
+                Reverse(p.last_playing),
+            )
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ActivePlayers;
+    use crate::mpris::{PlayerInfo, PlayerStatus};
+    use crate::player_filter::PlayerPattern;
+
+    fn active(players: &ActivePlayers) -> Option<&str> {
+        players.active().map(|p| p.sender.as_str())
+    }
+
+    #[test]
+    fn test_active_player_ranking() {
+        let spotify = PlayerInfo::default().with_bus_name("org.mpris.MediaPlayer2.spotify");
+        let mpv = PlayerInfo::default().with_bus_name("org.mpris.MediaPlayer2.mpv");
+        let firefox =
+            PlayerInfo::default().with_bus_name("org.mpris.MediaPlayer2.firefox.instance_1_23");
+        let mut players = ActivePlayers::new(&[PlayerPattern::new("spotify").unwrap()]);
+        assert_eq!(None, active(&players));
+
+        // The most recently started player is active
+        players.handle_status(":1.1", &mpv, PlayerStatus::Playing);
+        players.handle_status(":1.2", &firefox, PlayerStatus::Playing);
+        assert_eq!(Some(":1.2"), active(&players));
+
+        // Unless a player has priority
+        players.handle_status(":1.3", &spotify, PlayerStatus::Playing);
+        players.handle_status(":1.1", &mpv, PlayerStatus::Paused);
+        players.handle_status(":1.1", &mpv, PlayerStatus::Playing);
+        assert_eq!(Some(":1.3"), active(&players));
+
+        // Playing players outrank paused ones
+        players.handle_status(":1.3", &spotify, PlayerStatus::Paused);
+        assert_eq!(Some(":1.1"), active(&players));
+
+        // Priority applies once a player's name becomes known
+        players.handle_status(":1.3", &spotify, PlayerStatus::Playing);
+        players.handle_status(":1.4", &PlayerInfo::default(), PlayerStatus::Playing);
+        assert_eq!(Some(":1.3"), active(&players));
+        players.set_bus_name(":1.4", "org.mpris.MediaPlayer2.spotify");
+        assert_eq!(Some(":1.4"), active(&players));
+        players.remove(":1.4");
+
+        players.remove(":1.1");
+        players.remove(":1.2");
+        assert_eq!(Some(":1.3"), active(&players));
+        assert_eq!(1, players.players.len());
+    }
+}
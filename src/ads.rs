@@ -0,0 +1,99 @@
+use crate::configuration::Configuration;
+use crate::mpris::PlayerMetadata;
+use crate::pattern::Pattern;
+
+// Spotify's free tier and some web players publish ads as regular tracks.
+// Spotify gives them a track ID like `spotify:ad:...` (or an object path
+// under `/com/spotify/ad/`) and a blank artist, while others use titles such
+// as "Advertisement".
+
+pub struct AdDetector {
+    enabled: bool,
+    track_id_patterns: Vec<Pattern>,
+    title_patterns: Vec<Pattern>,
+    empty_artist: bool,
+}
+
+impl AdDetector {
+    pub fn new(configuration: &Configuration) -> Self {
+        Self {
+            enabled: configuration.detect_ads,
+            track_id_patterns: configuration.ad_track_id_patterns.clone(),
+            title_patterns: configuration.ad_title_patterns.clone(),
+            empty_artist: configuration.ad_empty_artist,
+        }
+    }
+
+    /// Whether the metadata describes an ad.
+    pub fn is_ad(&self, metadata: &PlayerMetadata) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let matches = |value: Option<&str>, patterns: &[Pattern]| {
+            value.is_some_and(|value| patterns.iter().any(|p| p.regex().is_match(value)))
+        };
+        let blank_artist = metadata.artists.is_some() && !metadata.has_artist();
+
+        matches(metadata.track_id.as_deref(), &self.track_id_patterns)
+            || matches(metadata.title.as_deref(), &self.title_patterns)
+            || (self.empty_artist && blank_artist)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AdDetector;
+    use crate::configuration::Configuration;
+    use crate::mpris::PlayerMetadata;
+
+    #[test]
+    fn test_is_ad() {
+        let detector = AdDetector::new(&Configuration::default());
+        let song = PlayerMetadata::default()
+            .with_track_id("spotify:track:3n3Ppam7vgaVa1iaRUc9Lp")
+            .with_title("Mr. Brightside")
+            .with_artists(&["The Killers"]);
+        assert!(!detector.is_ad(&song));
+        assert!(!detector.is_ad(&PlayerMetadata::default()));
+
+        let spotify_ad = PlayerMetadata::default()
+            .with_track_id("spotify:ad:000000012c603a6d")
+            .with_title("Spotify")
+            .with_artists(&[""]);
+        let brand_ad = PlayerMetadata::default()
+            .with_track_id("/com/spotify/ad/1234")
+            .with_title("Brand")
+            .with_artists(&["Brand"]);
+        let announced_ad = PlayerMetadata::default()
+            .with_track_id("/org/mpv/track/0")
+            .with_title("ADVERTISEMENT")
+            .with_artists(&["Sponsor"]);
+        assert!(detector.is_ad(&spotify_ad));
+        assert!(detector.is_ad(&brand_ad));
+        assert!(detector.is_ad(&announced_ad));
+
+        // Blank artists are only taken for ads when enabled, and tracks
+        // without any artist never are.
+        let jingle = PlayerMetadata::default()
+            .with_track_id("/org/mpv/track/0")
+            .with_title("Jingle")
+            .with_artists(&[" "]);
+        let untagged = PlayerMetadata::default()
+            .with_track_id("/org/mpv/track/1")
+            .with_title("01 Track 01");
+        assert!(!detector.is_ad(&jingle));
+        let empty_artist = AdDetector::new(&Configuration {
+            ad_empty_artist: true,
+            ..Configuration::default()
+        });
+        assert!(empty_artist.is_ad(&jingle));
+        assert!(!empty_artist.is_ad(&untagged));
+
+        let disabled = AdDetector::new(&Configuration {
+            detect_ads: false,
+            ..Configuration::default()
+        });
+        assert!(!disabled.is_ad(&spotify_ad));
+    }
+}
@@ -1,3 +1,10 @@
+use crate::formatter::{validate, TemplateError};
+use crate::media_type::{MediaSettings, MediaType};
+use crate::mpris::PlayerAction;
+use crate::notifier::{MarkupMode, ReplacePolicy};
+use crate::pattern::Pattern;
+use crate::player_filter::{ArbitrationPolicy, PlayerPattern};
+use crate::rewrite::RewriteRule;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use std::fs;
@@ -17,11 +24,22 @@ lazy_static! {
 pub enum ConfigurationError {
     #[error("error parsing configuration")]
     Parsing(#[from] toml::de::Error),
+
+    #[error("invalid format string `{field}` in {file}: {error}")]
+    Template {
+        file: String,
+        field: String,
+        error: TemplateError,
+    },
 }
 
 /// Configuration file used by mpris-notifier, located at
 /// `$HOME/config/mpris-notifier/config.toml`.
+///
+/// Keys missing from the file fall back to their default values, so that
+/// configuration files written by older versions keep loading.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
 pub struct Configuration {
     /// Format string for the notification subject text.
     ///
@@ -33,6 +51,20 @@ pub struct Configuration {
     /// Default: [DEFAULT_BODY_FORMAT]
     pub body_format: String,
 
+    /// Whether the message text is sent as markup, in which case metadata
+    /// values are escaped and the format string may contain tags such as
+    /// `<b>` or `<a href="{url}">`. One of "auto" (use markup if the
+    /// notification server supports it), "always" or "never".
+    ///
+    /// Default: [DEFAULT_BODY_MARKUP]
+    pub body_markup: MarkupMode,
+
+    /// Only log a warning for format strings containing unknown tokens or
+    /// malformed syntax, instead of failing to load the configuration.
+    ///
+    /// Default: [DEFAULT_LENIENT_TEMPLATES]
+    pub lenient_templates: bool,
+
     /// For fields including multiple entities (such as "artists"), this
     /// determines which character is used to join the strings.
     ///
@@ -58,25 +90,423 @@ pub struct Configuration {
     ///
     /// Default: [DEFAULT_COMMANDS]
     pub commands: Option<Vec<Vec<String>>>,
+
+    /// Transport-control buttons attached to each notification. Clicking a
+    /// button calls the matching MPRIS method on the player that produced
+    /// the notification. Valid entries are "previous", "play_pause" and
+    /// "next".
+    ///
+    /// Default: [DEFAULT_ACTIONS]
+    pub actions: Vec<PlayerAction>,
+
+    /// Which on-screen notification a new notification replaces. One of
+    /// "per_player" (each player replaces its own notification), "global"
+    /// (every notification replaces the last one) or "stack" (notifications
+    /// are never replaced).
+    ///
+    /// Default: [DEFAULT_REPLACE_POLICY]
+    pub replace_policy: ReplacePolicy,
+
+    /// Close a player's notification when that player pauses.
+    ///
+    /// Default: [DEFAULT_CLOSE_ON_PAUSE]
+    pub close_on_pause: bool,
+
+    /// Close a player's notification when that player stops.
+    ///
+    /// Default: [DEFAULT_CLOSE_ON_STOP]
+    pub close_on_stop: bool,
+
+    /// Close a player's notification when that player exits.
+    ///
+    /// Default: [DEFAULT_CLOSE_ON_EXIT]
+    pub close_on_exit: bool,
+
+    /// Players for which notifications are generated and commands are run.
+    /// Patterns are matched against the player's bus name (with or without
+    /// the `org.mpris.MediaPlayer2.` prefix) and its identity, either as
+    /// globs (`spotify*`), or as regular expressions when wrapped in slashes
+    /// (`/^chrom(e|ium)/`). When empty, all players are included.
+    ///
+    /// Default: [DEFAULT_PLAYER_INCLUDE]
+    pub player_include: Vec<PlayerPattern>,
+
+    /// Players which are ignored, given as patterns in the same format as
+    /// `player_include`. Exclusions take precedence over inclusions.
+    ///
+    /// Default: [DEFAULT_PLAYER_EXCLUDE]
+    pub player_exclude: Vec<PlayerPattern>,
+
+    /// Which notifications are sent when several players change at once.
+    /// One of "all", "most_recent" or "priority" (following
+    /// `player_priority`). Only track notifications (new track, resume,
+    /// pause, stop and ad breaks) are arbitrated; property change
+    /// notifications and `commands` are not.
+    ///
+    /// Default: [DEFAULT_ARBITRATION_POLICY]
+    pub arbitration_policy: ArbitrationPolicy,
+
+    /// Players in descending order of priority, given as patterns in the
+    /// same format as `player_include`. Used by the "priority" arbitration
+    /// policy.
+    ///
+    /// Default: [DEFAULT_PLAYER_PRIORITY]
+    pub player_priority: Vec<PlayerPattern>,
+
+    /// Show a notification at startup for a player which is already playing.
+    ///
+    /// Default: [DEFAULT_NOTIFY_ON_STARTUP]
+    pub notify_on_startup: bool,
+
+    /// Show a notification when a player starts playing a new track, using
+    /// `subject_format` and `body_format`.
+    ///
+    /// Default: [DEFAULT_NOTIFY_ON_NEW_TRACK]
+    pub notify_on_new_track: bool,
+
+    /// The window, in milliseconds, within which a track notified before
+    /// isn't notified again, even when it comes from another player (e.g. a
+    /// browser and KDE Connect mirroring the same track). Tracks are compared
+    /// by title, artists and album, ignoring case and whitespace. Set to 0 to
+    /// disable.
+    ///
+    /// Default: [DEFAULT_DUPLICATE_WINDOW]
+    pub duplicate_window: u32,
+
+    /// Show a notification when a player resumes the track it was last
+    /// notified for.
+    ///
+    /// Default: [DEFAULT_NOTIFY_ON_RESUME]
+    pub notify_on_resume: bool,
+
+    /// Format string for the subject of resume notifications.
+    ///
+    /// Default: [DEFAULT_RESUME_SUBJECT_FORMAT]
+    pub resume_subject_format: String,
+
+    /// Format string for the message text of resume notifications.
+    ///
+    /// Default: [DEFAULT_RESUME_BODY_FORMAT]
+    pub resume_body_format: String,
+
+    /// Show a notification when a player pauses. Ignored if `close_on_pause`
+    /// is set.
+    ///
+    /// Default: [DEFAULT_NOTIFY_ON_PAUSE]
+    pub notify_on_pause: bool,
+
+    /// Format string for the subject of pause notifications.
+    ///
+    /// Default: [DEFAULT_PAUSE_SUBJECT_FORMAT]
+    pub pause_subject_format: String,
+
+    /// Format string for the message text of pause notifications.
+    ///
+    /// Default: [DEFAULT_PAUSE_BODY_FORMAT]
+    pub pause_body_format: String,
+
+    /// Show a notification when a player stops. Ignored if `close_on_stop`
+    /// is set.
+    ///
+    /// Default: [DEFAULT_NOTIFY_ON_STOP]
+    pub notify_on_stop: bool,
+
+    /// Format string for the subject of stop notifications.
+    ///
+    /// Default: [DEFAULT_STOP_SUBJECT_FORMAT]
+    pub stop_subject_format: String,
+
+    /// Format string for the message text of stop notifications.
+    ///
+    /// Default: [DEFAULT_STOP_BODY_FORMAT]
+    pub stop_body_format: String,
+
+    /// Show a notification when a player toggles shuffle.
+    ///
+    /// Default: [DEFAULT_NOTIFY_ON_SHUFFLE]
+    pub notify_on_shuffle: bool,
+
+    /// Format string for the shuffle notification subject.
+    ///
+    /// Default: [DEFAULT_SHUFFLE_FORMAT]
+    pub shuffle_format: String,
+
+    /// Show a notification when a player changes its loop status.
+    ///
+    /// Default: [DEFAULT_NOTIFY_ON_LOOP_STATUS]
+    pub notify_on_loop_status: bool,
+
+    /// Format string for the loop status notification subject.
+    ///
+    /// Default: [DEFAULT_LOOP_STATUS_FORMAT]
+    pub loop_status_format: String,
+
+    /// Show a notification when a player changes its volume. The volume is
+    /// also sent as a progress value, which some servers draw as a bar.
+    ///
+    /// Default: [DEFAULT_NOTIFY_ON_VOLUME]
+    pub notify_on_volume: bool,
+
+    /// Format string for the volume notification subject.
+    ///
+    /// Default: [DEFAULT_VOLUME_FORMAT]
+    pub volume_format: String,
+
+    /// Show a notification when a player changes its playback rate.
+    ///
+    /// Default: [DEFAULT_NOTIFY_ON_RATE]
+    pub notify_on_rate: bool,
+
+    /// Format string for the playback rate notification subject.
+    ///
+    /// Default: [DEFAULT_RATE_FORMAT]
+    pub rate_format: String,
+
+    /// Split the titles of internet radio streams, which players often send
+    /// as "Artist - Song", into artist and title.
+    ///
+    /// Default: [DEFAULT_SPLIT_STREAM_TITLES]
+    pub split_stream_titles: bool,
+
+    /// Regular expression separating the artist from the title in stream
+    /// titles. Titles are split at the first match.
+    ///
+    /// Default: [DEFAULT_STREAM_TITLE_SEPARATOR]
+    pub stream_title_separator: Pattern,
+
+    /// Recognize advertisements, which some players publish as regular
+    /// tracks. Ads don't produce notifications, don't run `commands`, and
+    /// aren't remembered as the last notified track.
+    ///
+    /// Default: [DEFAULT_DETECT_ADS]
+    pub detect_ads: bool,
+
+    /// Regular expressions matching the track IDs of ads.
+    ///
+    /// Default: [DEFAULT_AD_TRACK_ID_PATTERNS]
+    pub ad_track_id_patterns: Vec<Pattern>,
+
+    /// Regular expressions matching the titles of ads.
+    ///
+    /// Default: [DEFAULT_AD_TITLE_PATTERNS]
+    pub ad_title_patterns: Vec<Pattern>,
+
+    /// Treat tracks which have an artist, but a blank one, as ads. Off by
+    /// default, as some players send blank artists for regular tracks.
+    ///
+    /// Default: [DEFAULT_AD_EMPTY_ARTIST]
+    pub ad_empty_artist: bool,
+
+    /// Show a single notification when a player starts playing ads.
+    ///
+    /// Default: [DEFAULT_NOTIFY_ON_AD_BREAK]
+    pub notify_on_ad_break: bool,
+
+    /// Format string for the subject of ad break notifications.
+    ///
+    /// Default: [DEFAULT_AD_BREAK_SUBJECT_FORMAT]
+    pub ad_break_subject_format: String,
+
+    /// Format string for the body of ad break notifications, where
+    /// `{player}` names the player playing the ads.
+    ///
+    /// Default: [DEFAULT_AD_BREAK_BODY_FORMAT]
+    pub ad_break_body_format: String,
+
+    /// Rules rewriting metadata fields before they are used, applied in
+    /// order. Each rule matches a field (e.g. "title") with a regular
+    /// expression, and replaces or strips the matched text, optionally
+    /// moving it to another field (e.g. "feat. X" from the title to the
+    /// artists).
+    ///
+    /// Default: [DEFAULT_REWRITE_RULES]
+    pub rewrite_rules: Vec<RewriteRule>,
+
+    /// Settings for music, overriding `subject_format`, `body_format` and
+    /// `enable_album_art` for new tracks, or disabling notifications.
+    ///
+    /// Default: [MediaSettings::default]
+    pub music: MediaSettings,
+
+    /// Settings for podcasts, see `music`.
+    ///
+    /// Default: [MediaSettings::default]
+    pub podcast: MediaSettings,
+
+    /// Settings for videos, see `music`.
+    ///
+    /// Default: [MediaSettings::default]
+    pub video: MediaSettings,
+
+    /// Settings for internet radio streams, see `music`.
+    ///
+    /// Default: [MediaSettings::default]
+    pub stream: MediaSettings,
 }
 
 const DEFAULT_SUBJECT_FORMAT: &str = "{track}";
+const DEFAULT_LENIENT_TEMPLATES: bool = false;
 const DEFAULT_BODY_FORMAT: &str = "{album} - {artist}";
+const DEFAULT_BODY_MARKUP: MarkupMode = MarkupMode::Auto;
 const DEFAULT_JOIN_STRING: &str = ", ";
 const DEFAULT_ENABLE_ALBUM_ART: bool = true;
 const DEFAULT_ALBUM_ART_DEADLINE: u32 = 1000;
 const DEFAULT_COMMANDS: Option<Vec<Vec<String>>> = None;
+const DEFAULT_ACTIONS: Vec<PlayerAction> = Vec::new();
+const DEFAULT_REPLACE_POLICY: ReplacePolicy = ReplacePolicy::PerPlayer;
+const DEFAULT_CLOSE_ON_PAUSE: bool = false;
+const DEFAULT_CLOSE_ON_STOP: bool = false;
+const DEFAULT_CLOSE_ON_EXIT: bool = false;
+const DEFAULT_PLAYER_INCLUDE: Vec<PlayerPattern> = Vec::new();
+const DEFAULT_PLAYER_EXCLUDE: Vec<PlayerPattern> = Vec::new();
+const DEFAULT_ARBITRATION_POLICY: ArbitrationPolicy = ArbitrationPolicy::All;
+const DEFAULT_PLAYER_PRIORITY: Vec<PlayerPattern> = Vec::new();
+const DEFAULT_NOTIFY_ON_STARTUP: bool = false;
+const DEFAULT_NOTIFY_ON_NEW_TRACK: bool = true;
+const DEFAULT_DUPLICATE_WINDOW: u32 = 5000;
+const DEFAULT_NOTIFY_ON_RESUME: bool = false;
+const DEFAULT_RESUME_SUBJECT_FORMAT: &str = "{track}";
+const DEFAULT_RESUME_BODY_FORMAT: &str = "{album} - {artist}";
+const DEFAULT_NOTIFY_ON_PAUSE: bool = false;
+const DEFAULT_PAUSE_SUBJECT_FORMAT: &str = "Paused";
+const DEFAULT_PAUSE_BODY_FORMAT: &str = "{track}";
+const DEFAULT_NOTIFY_ON_STOP: bool = false;
+const DEFAULT_STOP_SUBJECT_FORMAT: &str = "Stopped";
+const DEFAULT_STOP_BODY_FORMAT: &str = "{track}";
+const DEFAULT_NOTIFY_ON_SHUFFLE: bool = false;
+const DEFAULT_SHUFFLE_FORMAT: &str = "Shuffle {shuffle}";
+const DEFAULT_NOTIFY_ON_LOOP_STATUS: bool = false;
+const DEFAULT_LOOP_STATUS_FORMAT: &str = "Loop: {loop_status}";
+const DEFAULT_NOTIFY_ON_VOLUME: bool = false;
+const DEFAULT_VOLUME_FORMAT: &str = "Volume {volume}%";
+const DEFAULT_NOTIFY_ON_RATE: bool = false;
+const DEFAULT_RATE_FORMAT: &str = "Playback rate {rate}x";
+const DEFAULT_SPLIT_STREAM_TITLES: bool = true;
+const DEFAULT_STREAM_TITLE_SEPARATOR: &str = r"\s+[-–—]\s+";
+const DEFAULT_DETECT_ADS: bool = true;
+const DEFAULT_AD_TRACK_ID_PATTERNS: &[&str] = &["^spotify:ad:", "^/com/spotify/ad/"];
+const DEFAULT_AD_TITLE_PATTERNS: &[&str] = &["(?i)^advertisement$"];
+const DEFAULT_AD_EMPTY_ARTIST: bool = false;
+const DEFAULT_NOTIFY_ON_AD_BREAK: bool = false;
+const DEFAULT_AD_BREAK_SUBJECT_FORMAT: &str = "Ad break";
+const DEFAULT_AD_BREAK_BODY_FORMAT: &str = "{player}";
+const DEFAULT_REWRITE_RULES: Vec<RewriteRule> = Vec::new();
+
+// Compiles the built-in default patterns.
+fn patterns(sources: &[&str]) -> Vec<Pattern> {
+    sources
+        .iter()
+        .map(|source| Pattern::new(source).unwrap())
+        .collect()
+}
 
 impl Default for Configuration {
     fn default() -> Self {
         Self {
             subject_format: DEFAULT_SUBJECT_FORMAT.to_string(),
             body_format: DEFAULT_BODY_FORMAT.to_string(),
+            body_markup: DEFAULT_BODY_MARKUP,
+            lenient_templates: DEFAULT_LENIENT_TEMPLATES,
             join_string: DEFAULT_JOIN_STRING.to_string(),
             enable_album_art: DEFAULT_ENABLE_ALBUM_ART,
             album_art_deadline: DEFAULT_ALBUM_ART_DEADLINE,
             commands: DEFAULT_COMMANDS,
+            actions: DEFAULT_ACTIONS,
+            replace_policy: DEFAULT_REPLACE_POLICY,
+            close_on_pause: DEFAULT_CLOSE_ON_PAUSE,
+            close_on_stop: DEFAULT_CLOSE_ON_STOP,
+            close_on_exit: DEFAULT_CLOSE_ON_EXIT,
+            player_include: DEFAULT_PLAYER_INCLUDE,
+            player_exclude: DEFAULT_PLAYER_EXCLUDE,
+            arbitration_policy: DEFAULT_ARBITRATION_POLICY,
+            player_priority: DEFAULT_PLAYER_PRIORITY,
+            notify_on_startup: DEFAULT_NOTIFY_ON_STARTUP,
+            notify_on_new_track: DEFAULT_NOTIFY_ON_NEW_TRACK,
+            duplicate_window: DEFAULT_DUPLICATE_WINDOW,
+            notify_on_resume: DEFAULT_NOTIFY_ON_RESUME,
+            resume_subject_format: DEFAULT_RESUME_SUBJECT_FORMAT.to_string(),
+            resume_body_format: DEFAULT_RESUME_BODY_FORMAT.to_string(),
+            notify_on_pause: DEFAULT_NOTIFY_ON_PAUSE,
+            pause_subject_format: DEFAULT_PAUSE_SUBJECT_FORMAT.to_string(),
+            pause_body_format: DEFAULT_PAUSE_BODY_FORMAT.to_string(),
+            notify_on_stop: DEFAULT_NOTIFY_ON_STOP,
+            stop_subject_format: DEFAULT_STOP_SUBJECT_FORMAT.to_string(),
+            stop_body_format: DEFAULT_STOP_BODY_FORMAT.to_string(),
+            notify_on_shuffle: DEFAULT_NOTIFY_ON_SHUFFLE,
+            shuffle_format: DEFAULT_SHUFFLE_FORMAT.to_string(),
+            notify_on_loop_status: DEFAULT_NOTIFY_ON_LOOP_STATUS,
+            loop_status_format: DEFAULT_LOOP_STATUS_FORMAT.to_string(),
+            notify_on_volume: DEFAULT_NOTIFY_ON_VOLUME,
+            volume_format: DEFAULT_VOLUME_FORMAT.to_string(),
+            notify_on_rate: DEFAULT_NOTIFY_ON_RATE,
+            rate_format: DEFAULT_RATE_FORMAT.to_string(),
+            split_stream_titles: DEFAULT_SPLIT_STREAM_TITLES,
+            stream_title_separator: Pattern::new(DEFAULT_STREAM_TITLE_SEPARATOR).unwrap(),
+            detect_ads: DEFAULT_DETECT_ADS,
+            ad_track_id_patterns: patterns(DEFAULT_AD_TRACK_ID_PATTERNS),
+            ad_title_patterns: patterns(DEFAULT_AD_TITLE_PATTERNS),
+            ad_empty_artist: DEFAULT_AD_EMPTY_ARTIST,
+            notify_on_ad_break: DEFAULT_NOTIFY_ON_AD_BREAK,
+            ad_break_subject_format: DEFAULT_AD_BREAK_SUBJECT_FORMAT.to_string(),
+            ad_break_body_format: DEFAULT_AD_BREAK_BODY_FORMAT.to_string(),
+            rewrite_rules: DEFAULT_REWRITE_RULES,
+            music: MediaSettings::default(),
+            podcast: MediaSettings::default(),
+            video: MediaSettings::default(),
+            stream: MediaSettings::default(),
+        }
+    }
+}
+
+impl Configuration {
+    /// Settings for the given type of media.
+    pub fn media_settings(&self, media_type: MediaType) -> &MediaSettings {
+        match media_type {
+            MediaType::Music => &self.music,
+            MediaType::Podcast => &self.podcast,
+            MediaType::Video => &self.video,
+            MediaType::Stream => &self.stream,
+        }
+    }
+
+    // Every format string, along with its key.
+    fn templates(&self) -> Vec<(String, &str)> {
+        let mut templates: Vec<(String, &str)> = vec![
+            ("subject_format", &self.subject_format),
+            ("body_format", &self.body_format),
+            ("resume_subject_format", &self.resume_subject_format),
+            ("resume_body_format", &self.resume_body_format),
+            ("pause_subject_format", &self.pause_subject_format),
+            ("pause_body_format", &self.pause_body_format),
+            ("stop_subject_format", &self.stop_subject_format),
+            ("stop_body_format", &self.stop_body_format),
+            ("shuffle_format", &self.shuffle_format),
+            ("loop_status_format", &self.loop_status_format),
+            ("volume_format", &self.volume_format),
+            ("rate_format", &self.rate_format),
+            ("ad_break_subject_format", &self.ad_break_subject_format),
+            ("ad_break_body_format", &self.ad_break_body_format),
+        ]
+        .into_iter()
+        .map(|(field, template): (&str, &String)| (field.to_string(), template.as_str()))
+        .collect();
+
+        for (media, settings) in [
+            ("music", &self.music),
+            ("podcast", &self.podcast),
+            ("video", &self.video),
+            ("stream", &self.stream),
+        ] {
+            for (field, template) in [
+                ("subject_format", &settings.subject_format),
+                ("body_format", &settings.body_format),
+            ] {
+                if let Some(template) = template {
+                    templates.push((format!("{}.{}", media, field), template));
+                }
+            }
         }
+        templates
     }
 }
 
@@ -85,12 +515,37 @@ pub fn load_configuration() -> Result<Configuration, ConfigurationError> {
     load_configuration_from_path(&full_path)
 }
 
+// Checks every format string, failing on the first problem found, or only
+// logging the problems in lenient mode.
+fn validate_templates(config: &Configuration, full_path: &str) -> Result<(), ConfigurationError> {
+    for (field, template) in config.templates() {
+        for error in validate(template) {
+            if config.lenient_templates {
+                log::warn!(
+                    "Invalid format string `{}` in {}: {}",
+                    field,
+                    full_path,
+                    error
+                );
+            } else {
+                return Err(ConfigurationError::Template {
+                    file: full_path.to_string(),
+                    field,
+                    error,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
 // Loads a configuration. If a configuration file is not found, one is created
 // with default values, and the default values are used to start the program.
 fn load_configuration_from_path(full_path: &str) -> Result<Configuration, ConfigurationError> {
     // If we have an existing config file, try to load it and use that
     if let Ok(existing_toml) = fs::read_to_string(full_path) {
         let config: Configuration = toml::from_str(&existing_toml)?;
+        validate_templates(&config, full_path)?;
         return Ok(config);
     }
 
@@ -120,6 +575,13 @@ fn load_configuration_from_path(full_path: &str) -> Result<Configuration, Config
 #[cfg(test)]
 mod tests {
     use crate::configuration::{load_configuration_from_path, ConfigurationError};
+    use crate::formatter::TemplateError;
+    use crate::media_type::MediaSettings;
+    use crate::mpris::PlayerAction;
+    use crate::notifier::{MarkupMode, ReplacePolicy};
+    use crate::pattern::Pattern;
+    use crate::player_filter::{ArbitrationPolicy, PlayerPattern};
+    use crate::rewrite::{MetadataField, RewriteRule};
     use crate::Configuration;
     use lazy_static::lazy_static;
     use std::{fs, matches};
@@ -130,9 +592,25 @@ mod tests {
             TempDir::new().unwrap().into_path().display().to_string();
     }
 
+    // Writes the configuration to a file of the given name in the test
+    // directory, and tries to load it.
+    fn try_load_test_configuration(
+        name: &str,
+        conf_data: &str,
+    ) -> Result<Configuration, ConfigurationError> {
+        let conf_path = format!("{}{}", &*TEST_TEMP_DIR, name);
+        fs::create_dir_all(&*TEST_TEMP_DIR).expect("test setup failed");
+        fs::write(&conf_path, conf_data).expect("test setup failed");
+
+        load_configuration_from_path(&conf_path)
+    }
+
+    fn load_test_configuration(name: &str, conf_data: &str) -> Configuration {
+        try_load_test_configuration(name, conf_data).expect("expected valid configuration to load")
+    }
+
     #[test]
     fn test_load_configuration_existing_happy() {
-        let conf_path = format!("{}{}", &*TEST_TEMP_DIR, "happy.toml");
         let conf_data = r#"subject_format = '{track}'
                           body_format = "{album}\n{artist}"
                           join_string = ' ⬥ '
@@ -153,13 +631,219 @@ mod tests {
                 ],
                 vec!["~/script.sh".to_string()],
             ]),
+            ..Configuration::default()
         };
-        fs::create_dir_all(&*TEST_TEMP_DIR).expect("test setup failed");
-        fs::write(&conf_path, conf_data).expect("test setup failed");
+        assert_eq!(expected, load_test_configuration("happy.toml", conf_data));
+    }
+
+    #[test]
+    fn test_load_configuration_notification_behavior() {
+        let conf_data = r#"body_markup = 'never'
+                          lenient_templates = true
+                          actions = ['previous', 'play_pause', 'next']
+                          replace_policy = 'global'
+                          close_on_pause = true
+                          close_on_stop = true
+                          close_on_exit = true"#;
+        let expected = Configuration {
+            body_markup: MarkupMode::Never,
+            lenient_templates: true,
+            actions: vec![
+                PlayerAction::Previous,
+                PlayerAction::PlayPause,
+                PlayerAction::Next,
+            ],
+            replace_policy: ReplacePolicy::Global,
+            close_on_pause: true,
+            close_on_stop: true,
+            close_on_exit: true,
+            ..Configuration::default()
+        };
+        assert_eq!(
+            expected,
+            load_test_configuration("notification_behavior.toml", conf_data)
+        );
+    }
 
-        let result =
-            load_configuration_from_path(&conf_path).expect("expected valid configuration to load");
-        assert_eq!(expected, result);
+    #[test]
+    fn test_load_configuration_players() {
+        let conf_data = r#"player_include = ['spotify', 'mpv']
+                          player_exclude = ['firefox*', '/^chrom(e|ium)/']
+                          arbitration_policy = 'priority'
+                          player_priority = ['spotify', 'mpv']"#;
+        let expected = Configuration {
+            player_include: vec![
+                PlayerPattern::new("spotify").unwrap(),
+                PlayerPattern::new("mpv").unwrap(),
+            ],
+            player_exclude: vec![
+                PlayerPattern::new("firefox*").unwrap(),
+                PlayerPattern::new("/^chrom(e|ium)/").unwrap(),
+            ],
+            arbitration_policy: ArbitrationPolicy::Priority,
+            player_priority: vec![
+                PlayerPattern::new("spotify").unwrap(),
+                PlayerPattern::new("mpv").unwrap(),
+            ],
+            ..Configuration::default()
+        };
+        assert_eq!(expected, load_test_configuration("players.toml", conf_data));
+    }
+
+    #[test]
+    fn test_load_configuration_playback_events() {
+        let conf_data = r#"notify_on_startup = true
+                          notify_on_new_track = false
+                          duplicate_window = 10000
+                          notify_on_resume = true
+                          resume_subject_format = 'Resumed {track}'
+                          resume_body_format = '{artist}'
+                          notify_on_pause = true
+                          pause_subject_format = 'Paused {track}'
+                          pause_body_format = ''
+                          notify_on_stop = true
+                          stop_subject_format = 'Stopped {track}'
+                          stop_body_format = ''"#;
+        let expected = Configuration {
+            notify_on_startup: true,
+            notify_on_new_track: false,
+            duplicate_window: 10000,
+            notify_on_resume: true,
+            resume_subject_format: "Resumed {track}".to_string(),
+            resume_body_format: "{artist}".to_string(),
+            notify_on_pause: true,
+            pause_subject_format: "Paused {track}".to_string(),
+            pause_body_format: "".to_string(),
+            notify_on_stop: true,
+            stop_subject_format: "Stopped {track}".to_string(),
+            stop_body_format: "".to_string(),
+            ..Configuration::default()
+        };
+        assert_eq!(
+            expected,
+            load_test_configuration("playback_events.toml", conf_data)
+        );
+    }
+
+    #[test]
+    fn test_load_configuration_property_changes() {
+        let conf_data = r#"notify_on_shuffle = true
+                          shuffle_format = 'Shuffle: {shuffle}'
+                          notify_on_loop_status = true
+                          loop_status_format = 'Repeat {loop_status}'
+                          notify_on_volume = true
+                          volume_format = '{volume}%'
+                          notify_on_rate = true
+                          rate_format = '{rate}x'"#;
+        let expected = Configuration {
+            notify_on_shuffle: true,
+            shuffle_format: "Shuffle: {shuffle}".to_string(),
+            notify_on_loop_status: true,
+            loop_status_format: "Repeat {loop_status}".to_string(),
+            notify_on_volume: true,
+            volume_format: "{volume}%".to_string(),
+            notify_on_rate: true,
+            rate_format: "{rate}x".to_string(),
+            ..Configuration::default()
+        };
+        assert_eq!(
+            expected,
+            load_test_configuration("property_changes.toml", conf_data)
+        );
+    }
+
+    #[test]
+    fn test_load_configuration_streams() {
+        let conf_data = r#"split_stream_titles = false
+                          stream_title_separator = ' / '"#;
+        let expected = Configuration {
+            split_stream_titles: false,
+            stream_title_separator: Pattern::new(" / ").unwrap(),
+            ..Configuration::default()
+        };
+        assert_eq!(expected, load_test_configuration("streams.toml", conf_data));
+    }
+
+    #[test]
+    fn test_load_configuration_ads() {
+        let conf_data = r#"detect_ads = false
+                          ad_track_id_patterns = ['^spotify:ad:']
+                          ad_title_patterns = ['(?i)^advertisement$', '^Spotify$']
+                          ad_empty_artist = true
+                          notify_on_ad_break = true
+                          ad_break_subject_format = 'Ads on {player}'
+                          ad_break_body_format = ''"#;
+        let expected = Configuration {
+            detect_ads: false,
+            ad_track_id_patterns: vec![Pattern::new("^spotify:ad:").unwrap()],
+            ad_title_patterns: vec![
+                Pattern::new("(?i)^advertisement$").unwrap(),
+                Pattern::new("^Spotify$").unwrap(),
+            ],
+            ad_empty_artist: true,
+            notify_on_ad_break: true,
+            ad_break_subject_format: "Ads on {player}".to_string(),
+            ad_break_body_format: "".to_string(),
+            ..Configuration::default()
+        };
+        assert_eq!(expected, load_test_configuration("ads.toml", conf_data));
+    }
+
+    #[test]
+    fn test_load_configuration_rewrite_rules() {
+        let conf_data = r#"rewrite_rules = [
+                            { field = 'title', pattern = ' - Remastered \d{4}$' },
+                            { field = 'title', pattern = '\(feat\. ([^)]+)\)', move_to = 'artists' },
+                          ]"#;
+        let expected = Configuration {
+            rewrite_rules: vec![
+                RewriteRule {
+                    field: MetadataField::Title,
+                    pattern: Pattern::new(r" - Remastered \d{4}$").unwrap(),
+                    replace: "".to_string(),
+                    move_to: None,
+                },
+                RewriteRule {
+                    field: MetadataField::Title,
+                    pattern: Pattern::new(r"\(feat\. ([^)]+)\)").unwrap(),
+                    replace: "".to_string(),
+                    move_to: Some(MetadataField::Artist),
+                },
+            ],
+            ..Configuration::default()
+        };
+        assert_eq!(
+            expected,
+            load_test_configuration("rewrite_rules.toml", conf_data)
+        );
+    }
+
+    #[test]
+    fn test_load_configuration_media_types() {
+        let conf_data = r#"[podcast]
+                          subject_format = '{album}'
+                          body_format = '{title} ({length})'
+                          enable_album_art = false
+
+                          [video]
+                          notify = false"#;
+        let expected = Configuration {
+            podcast: MediaSettings {
+                notify: true,
+                subject_format: Some("{album}".to_string()),
+                body_format: Some("{title} ({length})".to_string()),
+                enable_album_art: Some(false),
+            },
+            video: MediaSettings {
+                notify: false,
+                ..MediaSettings::default()
+            },
+            ..Configuration::default()
+        };
+        assert_eq!(
+            expected,
+            load_test_configuration("media_types.toml", conf_data)
+        );
     }
 
     #[test]
@@ -174,6 +858,46 @@ mod tests {
         assert!(matches!(err, ConfigurationError::Parsing(_)));
     }
 
+    #[test]
+    fn test_load_configuration_invalid_pattern() {
+        let conf_data = "player_exclude = ['/(/']";
+        let err = try_load_test_configuration("invalid_pattern.toml", conf_data)
+            .expect_err("expected invalid pattern to fail to load");
+        assert!(matches!(err, ConfigurationError::Parsing(_)));
+    }
+
+    #[test]
+    fn test_load_configuration_invalid_template() {
+        let conf_data = "body_format = '{album} - {artsit}'";
+        let err = try_load_test_configuration("invalid_template.toml", conf_data)
+            .expect_err("expected unknown token to fail to load");
+        assert_eq!(
+            ConfigurationError::Template {
+                file: format!("{}{}", &*TEST_TEMP_DIR, "invalid_template.toml"),
+                field: "body_format".to_string(),
+                error: TemplateError::UnknownToken {
+                    token: "artsit".to_string(),
+                    column: 11,
+                },
+            },
+            err
+        );
+
+        let conf_data = format!("lenient_templates = true\n{}", conf_data);
+        let result = load_test_configuration("invalid_template.toml", &conf_data);
+        assert_eq!("{album} - {artsit}", result.body_format);
+    }
+
+    #[test]
+    fn test_load_configuration_missing_keys() {
+        let conf_data = "subject_format = '{title}'";
+        let expected = Configuration {
+            subject_format: "{title}".to_string(),
+            ..Configuration::default()
+        };
+        assert_eq!(expected, load_test_configuration("partial.toml", conf_data));
+    }
+
     #[test]
     fn test_load_configuration_default_fail_write_default() {
         let mut permissions = fs::metadata(&*TEST_TEMP_DIR).unwrap().permissions();
@@ -1,10 +1,14 @@
 use rustbus::message_builder::MarshalledMessage;
 use rustbus::DuplexConn;
-use std::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 const POLLING_TIMEOUT: Duration = Duration::from_millis(250);
 
+// Upper bound on how long a method call may block waiting for its reply.
+const CALL_TIMEOUT: Duration = Duration::from_millis(1000);
+
 #[derive(Debug, Error)]
 pub enum DBusError {
     #[error("D-Bus connection error")]
@@ -13,8 +17,11 @@ pub enum DBusError {
     #[error("Unexpected D-Bus message format")]
     Invalid(String),
 
-    #[error("D-Bus error message received")]
-    Generic(String),
+    #[error("D-Bus error reply {name}: {message}")]
+    MethodError { name: String, message: String },
+
+    #[error("timed out awaiting reply to D-Bus method call {0}")]
+    CallTimeout(String),
 
     #[error("error unmarshalling D-Bus message")]
     Unmarshal(#[from] rustbus::wire::errors::UnmarshalError),
@@ -23,57 +30,172 @@ pub enum DBusError {
     Marshal(#[from] rustbus::wire::errors::MarshalError),
 }
 
+const DBUS_INTERFACE: &str = "org.freedesktop.DBus";
+const DBUS_OBJECTPATH: &str = "/org/freedesktop/DBus";
+const NAME_OWNER_CHANGED_MEMBER: &str = "NameOwnerChanged";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+/// A change of ownership of a bus name, as announced by the bus. An empty
+/// `new_owner` means the name was released (for unique names, that the
+/// client disconnected).
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct NameOwnerChange {
+    pub name: String,
+    pub old_owner: String,
+    pub new_owner: String,
+}
+
+impl NameOwnerChange {
+    // Parses a `NameOwnerChanged` signal, yielding `None` for other signals.
+    pub fn from_signal(signal: &MarshalledMessage) -> Result<Option<Self>, DBusError> {
+        if signal.dynheader.interface.as_deref() != Some(DBUS_INTERFACE)
+            || signal.dynheader.member.as_deref() != Some(NAME_OWNER_CHANGED_MEMBER)
+        {
+            return Ok(None);
+        }
+
+        let (name, old_owner, new_owner): (String, String, String) = signal.body.parser().get3()?;
+        Ok(Some(Self {
+            name,
+            old_owner,
+            new_owner,
+        }))
+    }
+}
+
 pub struct DBusConnection {
     connection: DuplexConn,
+    router: MessageRouter,
+}
+
+// Sorts received messages into signals for the main loop and replies to
+// method calls. Kept apart from the connection so it can be tested without a
+// bus.
+#[derive(Default)]
+struct MessageRouter {
+    // Signals received while awaiting a method reply, to be handed out by
+    // [next_signal] before reading from the connection again.
+    pending_signals: VecDeque<MarshalledMessage>,
+
+    // Map from <Serial> -> <Method Name> for calls sent without awaiting
+    // their reply, so that failed calls can still be reported.
+    unawaited_calls: HashMap<u32, String>,
 }
 
 impl DBusConnection {
     pub fn new() -> Result<Self, DBusError> {
         let (connection, _) = Self::connect()?;
-        Ok(Self { connection })
+        Ok(Self {
+            connection,
+            router: MessageRouter::default(),
+        })
     }
 
     /// Blocks, awaiting the next signal from D-Bus, which is processed and
-    /// returned. No-op messages (messages from which no useful result is
-    /// derived) are silently acknowledged, and `next_message` will continue
-    /// to block until a message that yields a result is received, or the
-    /// polling timeout is reached.
+    /// returned. Signals buffered while awaiting a method reply are returned
+    /// first. Other messages (such as replies to calls which weren't
+    /// awaited) are acknowledged, and `next_signal` will continue to block
+    /// until a signal is received, or the polling timeout is reached.
     pub fn next_signal(&mut self) -> Result<Option<MarshalledMessage>, DBusError> {
-        use rustbus::{connection::Timeout, MessageType};
+        use rustbus::connection::Timeout;
 
         loop {
+            if let Some(signal) = self.router.pending_signals.pop_front() {
+                return Ok(Some(signal));
+            }
+
             let message = self
                 .connection
                 .recv
                 .get_next_message(Timeout::Duration(POLLING_TIMEOUT))?;
-            match message.typ {
-                MessageType::Signal => return Ok(Some(message)),
-                MessageType::Error => {
-                    let body = self.message_body_string(&message)?;
-                    return Err(DBusError::Generic(body.to_string()));
-                }
-                MessageType::Invalid => {
-                    let body = self.message_body_string(&message)?.to_string();
-                    return Err(DBusError::Invalid(body));
-                }
-                _ => {}
-            }
+            self.router.route(message, None)?;
         }
     }
 
+    /// Sends a message without awaiting a reply. Should the message be a
+    /// method call which fails, the error reply is logged.
     pub fn send_message(&mut self, message: &MarshalledMessage) -> Result<(), DBusError> {
-        self.connection
-            .send
-            .send_message_write_all(message)
-            .map(|_| ())
-            .map_err(DBusError::Connection)
+        let serial = self.connection.send.send_message_write_all(message)?;
+        if message.typ == rustbus::MessageType::Call {
+            self.router
+                .unawaited_calls
+                .insert(serial, method_name(message));
+        }
+        Ok(())
+    }
+
+    /// Sends a method call and blocks until its reply is received, or the
+    /// default call timeout is reached. See [call_with_timeout].
+    pub fn call(&mut self, message: &MarshalledMessage) -> Result<MarshalledMessage, DBusError> {
+        self.call_with_timeout(message, CALL_TIMEOUT)
+    }
+
+    /// Sends a method call and blocks until the reply matching its serial is
+    /// received, or the timeout is reached. Error replies are returned as
+    /// [DBusError::MethodError]. Signals received in the meantime are kept,
+    /// and returned by subsequent calls to `next_signal`.
+    pub fn call_with_timeout(
+        &mut self,
+        message: &MarshalledMessage,
+        timeout: Duration,
+    ) -> Result<MarshalledMessage, DBusError> {
+        use rustbus::connection::Timeout;
+
+        let serial = self.connection.send.send_message_write_all(message)?;
+        let recv = &mut self.connection.recv;
+        self.router
+            .await_reply(serial, &method_name(message), timeout, |remaining| {
+                recv.get_next_message(Timeout::Duration(remaining))
+            })
     }
 
-    fn message_body_string<'a>(
-        &self,
-        message: &'a MarshalledMessage,
-    ) -> Result<&'a str, DBusError> {
-        Ok(message.body.parser().get::<&str>()?)
+    /// Calls `org.freedesktop.DBus.Properties.GetAll` for an interface,
+    /// returning the reply, whose body is the dict of properties.
+    pub fn get_all_properties(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+    ) -> Result<MarshalledMessage, DBusError> {
+        let mut message = properties_call("GetAll", destination, path);
+        message.body.push_param(interface)?;
+        self.call(&message)
+    }
+
+    /// Calls `org.freedesktop.DBus.Properties.Get` for a single property,
+    /// returning the reply, whose body is the property's variant.
+    pub fn get_property(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        property: &str,
+    ) -> Result<MarshalledMessage, DBusError> {
+        let mut message = properties_call("Get", destination, path);
+        message.body.push_param(interface)?;
+        message.body.push_param(property)?;
+        self.call(&message)
+    }
+
+    /// Lists every name currently owned on the bus.
+    pub fn list_names(&mut self) -> Result<Vec<String>, DBusError> {
+        use rustbus::standard_messages::list_names;
+        let reply = self.call(&list_names())?;
+        Ok(reply.body.parser().get()?)
+    }
+
+    /// Returns the unique name of the connection owning the given name.
+    pub fn get_name_owner(&mut self, name: &str) -> Result<String, DBusError> {
+        use rustbus::MessageBuilder;
+        let mut message = MessageBuilder::new()
+            .call("GetNameOwner")
+            .at(DBUS_INTERFACE)
+            .on(DBUS_OBJECTPATH)
+            .with_interface(DBUS_INTERFACE)
+            .build();
+        message.body.push_param(name)?;
+        let reply = self.call(&message)?;
+        Ok(reply.body.parser().get()?)
     }
 
     fn connect() -> Result<(DuplexConn, String), DBusError> {
@@ -92,11 +214,277 @@ impl DBusConnection {
         member: &str,
         path: &str,
     ) -> Result<(), DBusError> {
+        self.add_match(&format!(
+            "interface='{interface}',member='{member}',path='{path}'"
+        ))
+    }
+
+    fn add_match(&mut self, rule: &str) -> Result<(), DBusError> {
         use rustbus::standard_messages::add_match;
-        let match_str = format!("interface='{interface}',member='{member}',path='{path}'");
-        self.connection
-            .send
-            .send_message_write_all(&add_match(&match_str))?;
-        Ok(())
+        self.send_message(&add_match(rule))
+    }
+}
+
+impl MessageRouter {
+    // Receives messages until the reply to the call with the given serial
+    // arrives, or the timeout is reached. `receive` blocks for at most the
+    // given duration.
+    fn await_reply(
+        &mut self,
+        serial: u32,
+        method: &str,
+        timeout: Duration,
+        mut receive: impl FnMut(Duration) -> Result<MarshalledMessage, rustbus::connection::Error>,
+    ) -> Result<MarshalledMessage, DBusError> {
+        use rustbus::connection::Error;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let received = match receive(remaining) {
+                Err(Error::TimedOut) => Err(DBusError::CallTimeout(method.to_string())),
+                result => result.map_err(DBusError::from),
+            }?;
+            if let Some(reply) = self.route(received, Some(serial))? {
+                return Ok(reply);
+            }
+            if remaining.is_zero() {
+                return Err(DBusError::CallTimeout(method.to_string()));
+            }
+        }
+    }
+
+    // Dispatches a received message: signals are buffered, and the reply to
+    // the `awaited` call is returned. Replies to calls which weren't awaited
+    // are dropped, logging errors.
+    fn route(
+        &mut self,
+        message: MarshalledMessage,
+        awaited: Option<u32>,
+    ) -> Result<Option<MarshalledMessage>, DBusError> {
+        use rustbus::MessageType;
+
+        let response_serial = message.dynheader.response_serial;
+        match message.typ {
+            MessageType::Signal => self.pending_signals.push_back(message),
+            MessageType::Reply if response_serial.is_some() && response_serial == awaited => {
+                return Ok(Some(message));
+            }
+            MessageType::Error if response_serial.is_some() && response_serial == awaited => {
+                return Err(method_error(&message));
+            }
+            MessageType::Reply | MessageType::Error => {
+                let method =
+                    response_serial.and_then(|serial| self.unawaited_calls.remove(&serial));
+                if message.typ == MessageType::Error {
+                    log::warn!(
+                        "D-Bus call {} failed: {}",
+                        method.as_deref().unwrap_or("(unknown)"),
+                        method_error(&message)
+                    );
+                }
+            }
+            MessageType::Invalid => {
+                let body = message_body_string(&message)?.to_string();
+                return Err(DBusError::Invalid(body));
+            }
+            MessageType::Call => {}
+        }
+        Ok(None)
+    }
+}
+
+fn message_body_string(message: &MarshalledMessage) -> Result<&str, DBusError> {
+    Ok(message.body.parser().get::<&str>()?)
+}
+
+fn method_error(message: &MarshalledMessage) -> DBusError {
+    DBusError::MethodError {
+        name: message.dynheader.error_name.clone().unwrap_or_default(),
+        message: message_body_string(message).unwrap_or_default().to_string(),
+    }
+}
+
+fn properties_call(member: &str, destination: &str, path: &str) -> MarshalledMessage {
+    use rustbus::MessageBuilder;
+    MessageBuilder::new()
+        .call(member)
+        .at(destination)
+        .on(path)
+        .with_interface(PROPERTIES_INTERFACE)
+        .build()
+}
+
+// Describes a method call for logging, e.g. `org.freedesktop.Notifications.Notify`.
+fn method_name(message: &MarshalledMessage) -> String {
+    format!(
+        "{}.{}",
+        message.dynheader.interface.as_deref().unwrap_or_default(),
+        message.dynheader.member.as_deref().unwrap_or_default()
+    )
+}
+
+/// Bus names to receive ownership changes for.
+#[derive(Debug, Clone, Copy)]
+pub enum NameMatch<'a> {
+    /// A single name.
+    Name(&'a str),
+    /// A name and the names below it, e.g. `org.mpris.MediaPlayer2` also
+    /// matches `org.mpris.MediaPlayer2.spotify`.
+    Namespace(&'a str),
+}
+
+fn name_owner_match_rule(names: NameMatch) -> String {
+    let arg0 = match names {
+        NameMatch::Name(name) => format!("arg0='{name}'"),
+        NameMatch::Namespace(namespace) => format!("arg0namespace='{namespace}'"),
+    };
+    format!(
+        "type='signal',interface='{DBUS_INTERFACE}',member='{NAME_OWNER_CHANGED_MEMBER}',\
+         path='{DBUS_OBJECTPATH}',{arg0}"
+    )
+}
+
+// Convenience method to subscribe a DBusConnection to ownership changes of
+// the given bus names, used to notice players and notification servers
+// coming and going. Without a filter, every client connecting to the bus
+// would wake us up.
+pub fn subscribe_name_owner_changes(
+    dbus: &mut DBusConnection,
+    names: NameMatch,
+) -> Result<(), DBusError> {
+    dbus.add_match(&name_owner_match_rule(names))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{name_owner_match_rule, DBusError, MessageRouter, NameMatch};
+    use rustbus::connection::Error;
+    use rustbus::message_builder::MarshalledMessage;
+    use rustbus::{MessageBuilder, MessageType};
+    use std::collections::VecDeque;
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn call(serial: u32) -> MarshalledMessage {
+        let mut call = MessageBuilder::new()
+            .call("GetAll")
+            .at("org.mpris.MediaPlayer2.spotify")
+            .on("/org/mpris/MediaPlayer2")
+            .with_interface("org.freedesktop.DBus.Properties")
+            .build();
+        call.dynheader.serial = Some(serial);
+        call
+    }
+
+    fn reply(serial: u32) -> MarshalledMessage {
+        call(serial).dynheader.make_response()
+    }
+
+    fn error_reply(serial: u32) -> MarshalledMessage {
+        let mut error = call(serial).dynheader.make_error_response(
+            "org.freedesktop.DBus.Error.ServiceUnknown",
+            Some("The name is not activatable".to_string()),
+        );
+        error.typ = MessageType::Error;
+        error
+    }
+
+    fn signal(member: &str) -> MarshalledMessage {
+        MessageBuilder::new()
+            .signal(
+                "org.freedesktop.DBus.Properties",
+                member,
+                "/org/mpris/MediaPlayer2",
+            )
+            .build()
+    }
+
+    // Hands out the given messages in order, then times out.
+    fn receiver(
+        messages: Vec<MarshalledMessage>,
+    ) -> impl FnMut(Duration) -> Result<MarshalledMessage, Error> {
+        let mut messages = VecDeque::from(messages);
+        move |_| messages.pop_front().ok_or(Error::TimedOut)
+    }
+
+    #[test]
+    fn test_await_reply_out_of_order() {
+        let mut router = MessageRouter::default();
+        router.unawaited_calls.insert(6, "Notify".to_string());
+
+        let messages = vec![reply(6), reply(8), reply(7)];
+        let result = router.await_reply(7, "GetAll", TIMEOUT, receiver(messages));
+        assert_eq!(Some(7), result.unwrap().dynheader.response_serial);
+        assert!(router.unawaited_calls.is_empty());
+    }
+
+    #[test]
+    fn test_await_reply_buffers_signals() {
+        let mut router = MessageRouter::default();
+        let messages = vec![signal("PropertiesChanged"), signal("Seeked"), reply(7)];
+        router
+            .await_reply(7, "GetAll", TIMEOUT, receiver(messages))
+            .unwrap();
+
+        let members: Vec<_> = router
+            .pending_signals
+            .iter()
+            .map(|signal| signal.dynheader.member.clone().unwrap())
+            .collect();
+        assert_eq!(vec!["PropertiesChanged", "Seeked"], members);
+    }
+
+    #[test]
+    fn test_await_reply_error() {
+        let mut router = MessageRouter::default();
+
+        // Errors for other calls are only logged
+        let messages = vec![error_reply(6), error_reply(7)];
+        let err = router
+            .await_reply(7, "GetAll", TIMEOUT, receiver(messages))
+            .unwrap_err();
+        match err {
+            DBusError::MethodError { name, message } => {
+                assert_eq!("org.freedesktop.DBus.Error.ServiceUnknown", name);
+                assert_eq!("The name is not activatable", message);
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_await_reply_timeout() {
+        let mut router = MessageRouter::default();
+        let err = router
+            .await_reply(
+                7,
+                "GetAll",
+                TIMEOUT,
+                receiver(vec![reply(6), signal("Seeked")]),
+            )
+            .unwrap_err();
+        assert!(matches!(err, DBusError::CallTimeout(method) if method == "GetAll"));
+        assert_eq!(1, router.pending_signals.len());
+
+        // A receiver which never times out by itself is cut off at the deadline
+        let err = router
+            .await_reply(7, "GetAll", Duration::ZERO, |_| Ok(signal("Seeked")))
+            .unwrap_err();
+        assert!(matches!(err, DBusError::CallTimeout(_)));
+    }
+
+    #[test]
+    fn test_name_owner_match_rule() {
+        assert_eq!(
+            "type='signal',interface='org.freedesktop.DBus',member='NameOwnerChanged',\
+             path='/org/freedesktop/DBus',arg0namespace='org.mpris.MediaPlayer2'",
+            name_owner_match_rule(NameMatch::Namespace("org.mpris.MediaPlayer2"))
+        );
+        assert!(
+            name_owner_match_rule(NameMatch::Name("org.freedesktop.Notifications"))
+                .ends_with(",arg0='org.freedesktop.Notifications'")
+        );
     }
 }
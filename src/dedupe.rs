@@ -0,0 +1,130 @@
+use crate::mpris::PlayerMetadata;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Some players mirror each other's tracks (e.g. a browser and KDE Connect),
+// and some re-send the same metadata with a new track ID when seeking. Tracks
+// are identified across players by their normalized title, artists and
+// album, ignoring case, whitespace and the order of the artists.
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Fingerprint {
+    title: String,
+    artists: Vec<String>,
+    album: String,
+}
+
+/// Fingerprints the track, or None if it has no title to go by.
+pub fn fingerprint(metadata: &PlayerMetadata) -> Option<Fingerprint> {
+    let title = normalize(metadata.title.as_deref()?);
+    if title.is_empty() {
+        return None;
+    }
+
+    let mut artists: Vec<String> = metadata
+        .artists
+        .iter()
+        .flatten()
+        .map(|artist| normalize(artist))
+        .filter(|artist| !artist.is_empty())
+        .collect();
+    artists.sort();
+    let album = metadata.album.as_deref().map(normalize).unwrap_or_default();
+
+    Some(Fingerprint {
+        title,
+        artists,
+        album,
+    })
+}
+
+fn normalize(value: &str) -> String {
+    value
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Tracks notified within the last `window`, from any player.
+pub struct RecentTracks {
+    window: Duration,
+    notified: HashMap<Fingerprint, Instant>,
+}
+
+impl RecentTracks {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            notified: HashMap::new(),
+        }
+    }
+
+    /// Whether duplicates are suppressed at all.
+    pub fn is_enabled(&self) -> bool {
+        !self.window.is_zero()
+    }
+
+    pub fn record(&mut self, fingerprint: Fingerprint, now: Instant) {
+        if !self.is_enabled() {
+            return;
+        }
+        let window = self.window;
+        self.notified.retain(|_, notified| now - *notified < window);
+        self.notified.insert(fingerprint, now);
+    }
+
+    pub fn contains(&self, fingerprint: &Fingerprint, now: Instant) -> bool {
+        self.notified
+            .get(fingerprint)
+            .is_some_and(|notified| now - *notified < self.window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fingerprint, RecentTracks};
+    use crate::mpris::PlayerMetadata;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_fingerprint() {
+        let original = PlayerMetadata::default()
+            .with_title("Under Pressure")
+            .with_artists(&["Queen", "David Bowie"])
+            .with_album("Hot Space");
+        let mirrored = PlayerMetadata::default()
+            .with_track_id("/org/kde/kdeconnect/1")
+            .with_title(" under  pressure")
+            .with_artists(&["David Bowie", "QUEEN"])
+            .with_album("Hot Space ");
+        assert_eq!(fingerprint(&original), fingerprint(&mirrored));
+
+        let live = PlayerMetadata::default()
+            .with_title("Under Pressure")
+            .with_artists(&["Queen", "David Bowie"])
+            .with_album("Live at Wembley");
+        assert_ne!(fingerprint(&original), fingerprint(&live));
+        assert_eq!(None, fingerprint(&PlayerMetadata::default()));
+    }
+
+    #[test]
+    fn test_recent_tracks() {
+        let start = Instant::now();
+        let heroes = PlayerMetadata::default()
+            .with_title("Heroes")
+            .with_artists(&["David Bowie"])
+            .with_album("Heroes");
+        let song = fingerprint(&heroes).unwrap();
+        let mut recent = RecentTracks::new(Duration::from_secs(5));
+        assert!(!recent.contains(&song, start));
+
+        recent.record(song.clone(), start);
+        assert!(recent.contains(&song, start + Duration::from_secs(4)));
+        assert!(!recent.contains(&song, start + Duration::from_secs(5)));
+
+        let mut disabled = RecentTracks::new(Duration::ZERO);
+        disabled.record(song.clone(), start);
+        assert!(!disabled.contains(&song, start));
+    }
+}
@@ -1,55 +1,615 @@
-use crate::mpris::PlayerMetadata;
-use lazy_static::lazy_static;
-use regex::{Regex, Replacer};
+use crate::mpris::{PlayerMetadata, PlayerProperties};
 use std::fmt;
+use thiserror::Error;
 
-lazy_static! {
-    static ref FORMAT_REGEX: Regex = Regex::new(r"(\{[^\}]+\})").unwrap();
-    static ref EMPTY_STR: String = String::from("");
+// Format strings substitute `{token}`s with values of the current track,
+// and additionally support:
+//
+// * sections, rendered only when a token has a value:
+//   `{?album}{album} - {/album}`
+// * fallbacks for tokens without a value: `{album|Unknown album}`
+// * filters: `{title|upper}`, `{title|truncate:40}`, `{artist|join:" & "}`,
+//   `{position|time:human}`
+// * `{{` and `}}` for literal braces
+//
+// Tokens which aren't recognized are left as-is, although [validate]
+// reports them, along with malformed syntax.
+
+/// A problem found in a format string. Columns count characters from 1.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("unknown token `{token}` at column {column}")]
+    UnknownToken { token: String, column: usize },
+
+    #[error("unterminated `{{` at column {column}")]
+    Unterminated { column: usize },
+
+    #[error("empty `{{}}` at column {column}")]
+    EmptyTag { column: usize },
+
+    #[error("invalid filter `{filter}` at column {column}")]
+    InvalidFilter { filter: String, column: usize },
+
+    #[error("section `{name}` opened at column {column} is never closed")]
+    UnclosedSection { name: String, column: usize },
+
+    #[error("`{{/{name}}}` at column {column} doesn't close an open section")]
+    UnmatchedSectionEnd { name: String, column: usize },
 }
 
 pub struct FormattedNotification<'a> {
     fmt: &'a str,
     metadata: &'a PlayerMetadata,
     join_str: &'a str,
+
+    // Playback properties, for `{shuffle}`, `{volume}`, etc.
+    properties: Option<&'a PlayerProperties>,
+
+    // Name of the active player, for `{player}`.
+    player: Option<&'a str>,
+
+    // Playback position in microseconds, for `{position}` and `{remaining}`.
+    position: Option<i64>,
+
+    // When set, substituted metadata values are markup-escaped, while the
+    // literal text of the format string is left as-is, so that it may
+    // contain markup such as `<b>{title}</b>`.
+    escape: bool,
 }
 
 impl fmt::Display for FormattedNotification<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
-        write!(f, "{}", FORMAT_REGEX.replace_all(self.fmt, self))
+        let mut dst = String::with_capacity(self.fmt.len());
+        self.render(&parse(self.fmt).0, &mut dst);
+        write!(f, "{}", dst)
+    }
+}
+
+// A parsed format string.
+#[derive(Debug, PartialEq)]
+enum Node {
+    Text(String),
+    Token(Token),
+    // `{?name}...{/name}`
+    Section {
+        name: String,
+        column: usize,
+        children: Vec<Node>,
+    },
+}
+
+// A `{name|modifier|...}` token. The source is kept, so that unrecognized
+// tokens can be written back as-is.
+#[derive(Debug, PartialEq)]
+struct Token {
+    source: String,
+    column: usize,
+    name: String,
+    modifiers: Vec<Modifier>,
+}
+
+// Modifiers are applied in order. Any modifier which isn't a filter is a
+// fallback, optionally quoted.
+#[derive(Debug, PartialEq)]
+enum Modifier {
+    Fallback(String),
+    Upper,
+    Lower,
+    Truncate(usize),
+    Escape,
+    Join(String),
+    Time(TimeFormat),
+}
+
+// How the `time` filter formats durations.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum TimeFormat {
+    // "m:ss", or "h:mm:ss" for durations of an hour or more
+    Auto,
+    // "m:ss", where minutes may exceed 59
+    MinutesSeconds,
+    // "h:mm:ss"
+    HoursMinutesSeconds,
+    // e.g. "1 h 5 min", "3 min 25 s"
+    Human,
+}
+
+impl TimeFormat {
+    fn parse(source: &str) -> Option<Self> {
+        match source {
+            "auto" => Some(TimeFormat::Auto),
+            "m:ss" => Some(TimeFormat::MinutesSeconds),
+            "h:mm:ss" => Some(TimeFormat::HoursMinutesSeconds),
+            "human" => Some(TimeFormat::Human),
+            _ => None,
+        }
+    }
+}
+
+impl Modifier {
+    fn parse(source: &str) -> Self {
+        let (name, arg) = match source.split_once(':') {
+            Some((name, arg)) => (name, Some(unquote(arg))),
+            None => (source, None),
+        };
+        match (name, arg) {
+            ("upper", None) => Modifier::Upper,
+            ("lower", None) => Modifier::Lower,
+            ("escape", None) => Modifier::Escape,
+            ("truncate", Some(arg)) => match arg.parse() {
+                Ok(length) => Modifier::Truncate(length),
+                Err(_) => Modifier::Fallback(unquote(source)),
+            },
+            ("join", Some(arg)) => Modifier::Join(arg),
+            ("time", Some(arg)) => match TimeFormat::parse(&arg) {
+                Some(format) => Modifier::Time(format),
+                None => Modifier::Fallback(unquote(source)),
+            },
+            _ => Modifier::Fallback(unquote(source)),
+        }
+    }
+
+    // Whether a modifier which was read as a fallback looks like a filter
+    // with a missing or invalid argument, e.g. `truncate:abc`.
+    fn is_malformed_filter(&self, source: &str) -> bool {
+        let name = source.split(':').next().unwrap_or_default();
+        matches!(self, Modifier::Fallback(_))
+            && ["upper", "lower", "escape", "truncate", "join", "time"].contains(&name)
+    }
+}
+
+// Strips the quotes from a `"quoted"` argument, which may contain `\"` and
+// `\\` escapes. Unquoted arguments are returned as-is.
+fn unquote(source: &str) -> String {
+    let inner = match source
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        Some(inner) => inner,
+        _ => return source.to_string(),
+    };
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next @ ('"' | '\\'))) => {
+                unquoted.push(next);
+                chars.next();
+            }
+            _ => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+// Splits on a delimiter, ignoring delimiters within quotes.
+fn split_unquoted(source: &str, delimiter: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in source.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if c == delimiter && !quoted => {
+                parts.push(&source[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => (),
+        }
+    }
+    parts.push(&source[start..]);
+    parts
+}
+
+// Finds the `}` closing a tag which starts at the beginning of `source`,
+// skipping over quoted arguments. Tags can't contain an unquoted `{`.
+fn find_tag_end(source: &str) -> Option<usize> {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in source.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '}' if !quoted => return Some(i),
+            '{' if !quoted => return None,
+            _ => (),
+        }
+    }
+    None
+}
+
+// An open `{?name}` section, while parsing.
+struct Frame {
+    source: String,
+    column: usize,
+    name: String,
+    children: Vec<Node>,
+}
+
+// Parses a format string. Parsing never fails: malformed tags, such as an
+// unterminated `{`, or a section which is never closed, are kept as text,
+// and reported alongside the parsed nodes.
+fn parse(fmt: &str) -> (Vec<Node>, Vec<TemplateError>) {
+    let mut errors = Vec::new();
+    let mut stack = vec![Frame {
+        source: String::new(),
+        column: 0,
+        name: String::new(),
+        children: Vec::new(),
+    }];
+    let mut text = String::new();
+    let mut rest = fmt;
+
+    fn flush(text: &mut String, stack: &mut [Frame]) {
+        if !text.is_empty() {
+            let frame = stack.last_mut().unwrap();
+            frame.children.push(Node::Text(std::mem::take(text)));
+        }
+    }
+
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            text.push(c);
+            rest = &rest[2..];
+            continue;
+        }
+        let column = fmt[..fmt.len() - rest.len()].chars().count() + 1;
+        let end = match (c, find_tag_end(rest)) {
+            ('{', Some(end)) if end > 1 => end,
+            ('{', Some(_)) => {
+                errors.push(TemplateError::EmptyTag { column });
+                text.push_str("{}");
+                rest = &rest[2..];
+                continue;
+            }
+            ('{', None) => {
+                errors.push(TemplateError::Unterminated { column });
+                text.push(c);
+                rest = &rest[1..];
+                continue;
+            }
+            _ => {
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+        };
+        let source = &rest[..=end];
+        let content = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if let Some(name) = content.strip_prefix('?') {
+            flush(&mut text, &mut stack);
+            stack.push(Frame {
+                source: source.to_string(),
+                column,
+                name: name.to_string(),
+                children: Vec::new(),
+            });
+        } else if let Some(name) = content.strip_prefix('/') {
+            if stack.len() > 1 && stack.last().unwrap().name == name {
+                flush(&mut text, &mut stack);
+                let frame = stack.pop().unwrap();
+                stack.last_mut().unwrap().children.push(Node::Section {
+                    name: frame.name,
+                    column: frame.column,
+                    children: frame.children,
+                });
+            } else {
+                errors.push(TemplateError::UnmatchedSectionEnd {
+                    name: name.to_string(),
+                    column,
+                });
+                text.push_str(source);
+            }
+        } else {
+            flush(&mut text, &mut stack);
+            let mut parts = split_unquoted(content, '|').into_iter();
+            let name = parts.next().unwrap_or_default().to_string();
+            let mut modifiers = Vec::new();
+            for part in parts {
+                let modifier = Modifier::parse(part);
+                if modifier.is_malformed_filter(part) {
+                    errors.push(TemplateError::InvalidFilter {
+                        filter: part.to_string(),
+                        column,
+                    });
+                }
+                modifiers.push(modifier);
+            }
+            let token = Token {
+                source: source.to_string(),
+                column,
+                name,
+                modifiers,
+            };
+            stack.last_mut().unwrap().children.push(Node::Token(token));
+        }
     }
+    flush(&mut text, &mut stack);
+
+    // Sections which were never closed are kept as text, with their
+    // contents rendered as usual.
+    while stack.len() > 1 {
+        let frame = stack.pop().unwrap();
+        errors.push(TemplateError::UnclosedSection {
+            name: frame.name,
+            column: frame.column,
+        });
+        let parent = &mut stack.last_mut().unwrap().children;
+        parent.push(Node::Text(frame.source));
+        parent.extend(frame.children);
+    }
+    (stack.pop().unwrap().children, errors)
+}
+
+/// Whether a format string refers to any of the given tokens, either
+/// directly or as a section.
+pub fn references(fmt: &str, names: &[&str]) -> bool {
+    fn any(nodes: &[Node], names: &[&str]) -> bool {
+        nodes.iter().any(|node| match node {
+            Node::Text(_) => false,
+            Node::Token(token) => names.contains(&token.name.as_str()),
+            Node::Section { name, children, .. } => {
+                names.contains(&name.as_str()) || any(children, names)
+            }
+        })
+    }
+    any(&parse(fmt).0, names)
 }
 
-impl Replacer for &FormattedNotification<'_> {
-    fn replace_append(&mut self, caps: &regex::Captures<'_>, dst: &mut String) {
-        let cap = caps.get(0).unwrap().as_str();
+/// Checks a format string for unknown tokens and malformed syntax, which
+/// would otherwise be shown as-is in notifications.
+pub fn validate(fmt: &str) -> Vec<TemplateError> {
+    let (nodes, mut errors) = parse(fmt);
+    let metadata = PlayerMetadata::default();
+    let formatter = FormattedNotification::new(fmt, &metadata, "");
+
+    fn check(formatter: &FormattedNotification, nodes: &[Node], errors: &mut Vec<TemplateError>) {
+        for node in nodes {
+            let (name, column) = match node {
+                Node::Text(_) => continue,
+                Node::Token(token) => (&token.name, token.column),
+                Node::Section {
+                    name,
+                    column,
+                    children,
+                } => {
+                    check(formatter, children, errors);
+                    (name, *column)
+                }
+            };
+            if formatter.value(name).is_none() {
+                errors.push(TemplateError::UnknownToken {
+                    token: name.clone(),
+                    column,
+                });
+            }
+        }
+    }
+    check(&formatter, &nodes, &mut errors);
+    errors.sort_by_key(|err| match err {
+        TemplateError::UnknownToken { column, .. }
+        | TemplateError::Unterminated { column }
+        | TemplateError::EmptyTag { column }
+        | TemplateError::InvalidFilter { column, .. }
+        | TemplateError::UnclosedSection { column, .. }
+        | TemplateError::UnmatchedSectionEnd { column, .. } => *column,
+    });
+    errors
+}
+
+impl FormattedNotification<'_> {
+    fn render(&self, nodes: &[Node], dst: &mut String) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => dst.push_str(text),
+                Node::Token(token) => self.render_token(token, dst),
+                Node::Section { name, children, .. } => {
+                    if self.value(name).is_some_and(|values| !values.is_empty()) {
+                        self.render(children, dst);
+                    }
+                }
+            }
+        }
+    }
+
+    fn render_token(&self, token: &Token, dst: &mut String) {
+        let mut values = match self.value(&token.name) {
+            Some(values) => values,
+            None => {
+                // if we don't recognize the token, leave it as-is
+                dst.push_str(&token.source);
+                return;
+            }
+        };
+
+        let mut escape = self.escape;
+        for modifier in &token.modifiers {
+            match modifier {
+                Modifier::Fallback(fallback) => {
+                    if values.is_empty() {
+                        values = vec![fallback.clone()];
+                    }
+                }
+                Modifier::Upper => values = values.iter().map(|v| v.to_uppercase()).collect(),
+                Modifier::Lower => values = values.iter().map(|v| v.to_lowercase()).collect(),
+                Modifier::Truncate(length) => {
+                    values = vec![truncate(&values.join(self.join_str), *length)];
+                }
+                Modifier::Escape => escape = true,
+                Modifier::Join(join_str) => values = vec![values.join(join_str)],
+                Modifier::Time(format) => {
+                    // Only applies to durations, and not to their fallbacks
+                    if let Some(micros) = self.duration(&token.name) {
+                        values = vec![format_duration(micros, *format)];
+                    }
+                }
+            }
+        }
+
+        let value = values.join(self.join_str);
+        if escape {
+            dst.push_str(&escape_markup(&value));
+        } else {
+            dst.push_str(&value);
+        }
+    }
+
+    // The value of a duration token, in microseconds.
+    fn duration(&self, name: &str) -> Option<i64> {
+        match name {
+            "length" => self.metadata.length,
+            "position" => self.position,
+            "remaining" => Some((self.metadata.length? - self.position?).max(0)),
+            _ => None,
+        }
+    }
+
+    // The values of a token, which are empty if the field isn't set, or
+    // `None` if the token isn't recognized. Lists have one value per entry.
+    fn value(&self, name: &str) -> Option<Vec<String>> {
         let md = &self.metadata;
-        match cap {
-            "{album}" => dst.push_str(unwrap_str_field(&md.album)),
-            "{album_artists}" => dst.push_str(&unwrap_vec_field(&md.album_artists, self.join_str)),
-            "{album_artist}" => dst.push_str(&unwrap_vec_field(&md.album_artists, self.join_str)),
-            "{artists}" => dst.push_str(&unwrap_vec_field(&md.artists, self.join_str)),
-            "{artist}" => dst.push_str(&unwrap_vec_field(&md.artists, self.join_str)),
-            "{title}" => dst.push_str(unwrap_str_field(&md.title)),
-            "{track}" => dst.push_str(unwrap_str_field(&md.title)),
-            "{track_number}" => dst.push_str(&self.metadata.track_number.unwrap_or(1).to_string()),
-            _ => dst.push_str(cap), // if we don't recognize the token, leave it as-is
+        let properties = self.properties;
+        let values = match name {
+            "album" => str_field(&md.album),
+            "album_artists" | "album_artist" => vec_field(&md.album_artists),
+            "artists" | "artist" => vec_field(&md.artists),
+            "title" | "track" => str_field(&md.title),
+            "track_number" => num_field(&md.track_number),
+            "url" => str_field(&md.track_url),
+            "station" => str_field(&md.station),
+            "length" | "position" | "remaining" => num_field(
+                &self
+                    .duration(name)
+                    .map(|micros| format_duration(micros, TimeFormat::Auto)),
+            ),
+            "genre" | "genres" => vec_field(&md.genres),
+            "disc_number" => num_field(&md.disc_number),
+            "composer" | "composers" => vec_field(&md.composers),
+            "lyricist" | "lyricists" => vec_field(&md.lyricists),
+            "comment" => vec_field(&md.comments),
+            "content_created" => str_field(&md.content_created),
+            "year" => num_field(&year(&md.content_created)),
+            "user_rating" => num_field(&md.user_rating),
+            "use_count" => num_field(&md.use_count),
+            "bpm" => num_field(&md.audio_bpm),
+            "musicbrainz_track_id" => str_field(&md.musicbrainz_track_id),
+            "musicbrainz_album_id" => str_field(&md.musicbrainz_album_id),
+            "musicbrainz_artist_id" => vec_field(&md.musicbrainz_artist_ids),
+            "musicbrainz_album_artist_id" => vec_field(&md.musicbrainz_album_artist_ids),
+            "shuffle" => num_field(&properties.and_then(|p| p.shuffle).map(|shuffle| {
+                if shuffle {
+                    "on"
+                } else {
+                    "off"
+                }
+            })),
+            "loop_status" => num_field(
+                &properties
+                    .and_then(|p| p.loop_status)
+                    .map(|status| status.as_str()),
+            ),
+            "volume" => num_field(&properties.and_then(|p| p.volume).map(percentage)),
+            "rate" => num_field(&properties.and_then(|p| p.rate)),
+            "player" => num_field(&self.player),
+            // Arbitrary metadata keys, e.g. `{meta:xesam:asText}`
+            _ => match name.strip_prefix("meta:") {
+                Some(key) => vec_field(&md.extra.get(key).cloned()),
+                None => return None,
+            },
+        };
+        Some(values)
+    }
+}
+
+fn str_field(field: &Option<String>) -> Vec<String> {
+    vec_field(&field.as_ref().map(|value| vec![value.clone()]))
+}
+
+// Empty entries are dropped, so that a list of empty strings counts as
+// unset.
+fn vec_field(field: &Option<Vec<String>>) -> Vec<String> {
+    field
+        .iter()
+        .flatten()
+        .filter(|value| !value.is_empty())
+        .cloned()
+        .collect()
+}
+
+fn num_field<T: fmt::Display>(field: &Option<T>) -> Vec<String> {
+    str_field(&field.as_ref().map(|x| x.to_string()))
+}
+
+// Shortens text to at most the given number of characters, ending it with an
+// ellipsis if it was cut.
+fn truncate(text: &str, length: usize) -> String {
+    if text.chars().count() <= length {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(length.saturating_sub(1)).collect();
+    truncated = truncated.trim_end().to_string();
+    if length > 0 {
+        truncated.push('…');
+    }
+    truncated
+}
+
+/// Converts a fraction (e.g. volume, where 1.0 is full) to a whole
+/// percentage.
+pub fn percentage(fraction: f64) -> i32 {
+    (fraction * 100.0).round() as i32
+}
+
+// Formats a duration given in microseconds.
+fn format_duration(micros: i64, format: TimeFormat) -> String {
+    let seconds = micros.max(0) / 1_000_000;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match format {
+        TimeFormat::Auto if hours > 0 => format_duration(micros, TimeFormat::HoursMinutesSeconds),
+        TimeFormat::Auto | TimeFormat::MinutesSeconds => {
+            format!("{}:{seconds:02}", hours * 60 + minutes)
         }
+        TimeFormat::HoursMinutesSeconds => format!("{hours}:{minutes:02}:{seconds:02}"),
+        TimeFormat::Human => match (hours, minutes, seconds) {
+            (0, 0, seconds) => format!("{seconds} s"),
+            (0, minutes, 0) => format!("{minutes} min"),
+            (0, minutes, seconds) => format!("{minutes} min {seconds} s"),
+            (hours, 0, _) => format!("{hours} h"),
+            (hours, minutes, _) => format!("{hours} h {minutes} min"),
+        },
     }
 }
 
-fn unwrap_str_field(field: &Option<String>) -> &str {
-    field.as_ref().unwrap_or(&EMPTY_STR)
+// The year of an ISO 8601 date, such as "2007-04-30T00:00:00Z" or "2007".
+// Players may send anything, including multi-byte characters within the
+// first four bytes.
+fn year(date: &Option<String>) -> Option<&str> {
+    date.as_deref()
+        .and_then(|date| date.get(..4))
+        .filter(|year| year.bytes().all(|b| b.is_ascii_digit()))
 }
 
-// An owned String is returned, because joining the strings necessitates a new
-// allocation.
-fn unwrap_vec_field(field: &Option<Vec<String>>, join_str: &str) -> String {
-    if let Some(entries) = field {
-        entries.join(join_str)
-    } else {
-        "".to_string()
+/// Escapes text for notification servers which parse body markup (a subset
+/// of HTML, see the `body-markup` capability).
+pub fn escape_markup(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
     }
+    escaped
 }
 
 impl<'a> FormattedNotification<'a> {
@@ -58,14 +618,54 @@ impl<'a> FormattedNotification<'a> {
             fmt,
             metadata,
             join_str,
+            properties: None,
+            player: None,
+            position: None,
+            escape: false,
         }
     }
+
+    /// Provides the playback position, in microseconds, for `{position}`
+    /// and `{remaining}`.
+    pub fn with_position(mut self, position: Option<i64>) -> Self {
+        self.position = position;
+        self
+    }
+
+    /// Provides the name of the active player for `{player}`.
+    pub fn with_player(mut self, player: Option<&'a str>) -> Self {
+        self.player = player;
+        self
+    }
+
+    /// Provides playback properties for property tokens such as `{volume}`.
+    pub fn with_properties(mut self, properties: &'a PlayerProperties) -> Self {
+        self.properties = Some(properties);
+        self
+    }
+
+    /// Enables markup-escaping of substituted metadata values.
+    pub fn with_markup(mut self, escape: bool) -> Self {
+        self.escape = escape;
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::FormattedNotification;
-    use crate::mpris::PlayerMetadata;
+    use super::{
+        escape_markup, format_duration, parse, validate, FormattedNotification, Node,
+        TemplateError, TimeFormat,
+    };
+    use crate::mpris::{LoopStatus, PlayerMetadata, PlayerProperties};
+
+    #[test]
+    fn test_escape_markup() {
+        assert_eq!(
+            "Simon &amp; Garfunkel &lt;3 &quot;live&quot; &apos;66",
+            escape_markup("Simon & Garfunkel <3 \"live\" '66")
+        );
+    }
 
     #[test]
     fn test_formatted_notification() {
@@ -86,6 +686,7 @@ mod tests {
             title: Some("vivisect".to_string()),
             track_number: Some(1),
             track_url: Some("https://open.spotify.com/track/4C4YkH503GMmFv4gZ5cuXv".to_string()),
+            ..PlayerMetadata::default()
         };
         let join_str = " * ";
         let notification = FormattedNotification::new(fmt, &metadata, join_str);
@@ -94,10 +695,136 @@ mod tests {
         assert_eq!(exp, result);
     }
 
+    #[test]
+    fn test_formatted_notification_markup() {
+        let fmt = "<b>{title}</b> <a href=\"{url}\">{artist}</a> & {nop}";
+        let exp = "<b>Mrs. Robinson</b> <a href=\"https://example.com/?a=1&amp;b=2\">Simon &amp; Garfunkel</a> & {nop}";
+        let metadata = PlayerMetadata {
+            track_id: None,
+            album: None,
+            album_artists: None,
+            art_url: None,
+            artists: Some(vec!["Simon & Garfunkel".to_string()]),
+            title: Some("Mrs. Robinson".to_string()),
+            track_number: None,
+            track_url: Some("https://example.com/?a=1&b=2".to_string()),
+            ..PlayerMetadata::default()
+        };
+        let result = FormattedNotification::new(fmt, &metadata, ", ")
+            .with_markup(true)
+            .to_string();
+
+        assert_eq!(exp, result);
+    }
+
+    #[test]
+    fn test_formatted_notification_extended() {
+        let fmt = "{composer} ({year}) CD{disc_number} {length} {bpm}bpm {genre} {user_rating}";
+        let exp = "Johann Sebastian Bach (1987) CD2 1:02:03 120bpm Baroque, Classical 0.8";
+        let metadata = PlayerMetadata {
+            composers: Some(vec!["Johann Sebastian Bach".to_string()]),
+            content_created: Some("1987-03-01T00:00:00Z".to_string()),
+            disc_number: Some(2),
+            length: Some(3_723_000_000),
+            audio_bpm: Some(120),
+            genres: Some(vec!["Baroque".to_string(), "Classical".to_string()]),
+            user_rating: Some(0.8),
+            ..PlayerMetadata::default()
+        };
+        let result = FormattedNotification::new(fmt, &metadata, ", ").to_string();
+
+        assert_eq!(exp, result);
+        assert_eq!("4:05", format_duration(245_999_999, TimeFormat::Auto));
+    }
+
+    #[test]
+    fn test_formatted_notification_year() {
+        let year = |date: &str| {
+            let metadata = PlayerMetadata {
+                content_created: Some(date.to_string()),
+                ..PlayerMetadata::default()
+            };
+            FormattedNotification::new("{year|?}", &metadata, ", ").to_string()
+        };
+        assert_eq!("2007", year("2007-04-30T00:00:00Z"));
+        assert_eq!("2007", year("2007"));
+        assert_eq!("?", year("07"));
+        assert_eq!("?", year("20€5"));
+        assert_eq!("?", year("２００７年"));
+    }
+
+    #[test]
+    fn test_formatted_notification_durations() {
+        let fmt = "{position}/{length} -{remaining|time:human} {length|time:m:ss} \
+                   {position|time:h:mm:ss} {nop|time:human|?}";
+        let exp = "1:01:01/1:02:03 -1 min 2 s 62:03 1:01:01 {nop|time:human|?}";
+        let metadata = PlayerMetadata {
+            length: Some(3_723_000_000),
+            ..PlayerMetadata::default()
+        };
+        let result = FormattedNotification::new(fmt, &metadata, ", ")
+            .with_position(Some(3_661_000_000))
+            .to_string();
+        assert_eq!(exp, result);
+
+        assert!(super::references(
+            "{?remaining}-{/remaining}",
+            &["position", "remaining"]
+        ));
+        assert!(!super::references("{length}", &["position", "remaining"]));
+
+        // Without a position, there's nothing remaining either
+        let fmt = "{?position}{position} / {/position}{length}{remaining|time:human|-}";
+        let result = FormattedNotification::new(fmt, &metadata, ", ").to_string();
+        assert_eq!("1:02:03-", result);
+
+        assert_eq!("45 s", format_duration(45_000_000, TimeFormat::Human));
+        assert_eq!("3 min", format_duration(180_000_000, TimeFormat::Human));
+        assert_eq!("2 h", format_duration(7_230_000_000, TimeFormat::Human));
+        assert_eq!("0:00", format_duration(-1, TimeFormat::Auto));
+    }
+
+    #[test]
+    fn test_formatted_notification_properties() {
+        let fmt = "{player}: shuffle {shuffle}, repeat {loop_status}, volume {volume}%, {rate}x";
+        let exp = "mpv: shuffle on, repeat Playlist, volume 40%, 1.5x";
+        let metadata = PlayerMetadata::default();
+        let properties = PlayerProperties {
+            shuffle: Some(true),
+            loop_status: Some(LoopStatus::Playlist),
+            volume: Some(0.401),
+            rate: Some(1.5),
+            ..PlayerProperties::default()
+        };
+        let result = FormattedNotification::new(fmt, &metadata, ", ")
+            .with_properties(&properties)
+            .with_player(Some("mpv"))
+            .to_string();
+
+        assert_eq!(exp, result);
+    }
+
+    #[test]
+    fn test_formatted_notification_meta() {
+        let fmt = "{meta:mpv:chapter} / {meta:kde:tags} / {meta:missing}";
+        let exp = "Intro / live * bootleg / ";
+        let mut metadata = PlayerMetadata::default();
+        metadata
+            .extra
+            .insert("mpv:chapter".to_string(), vec!["Intro".to_string()]);
+        metadata.extra.insert(
+            "kde:tags".to_string(),
+            vec!["live".to_string(), "bootleg".to_string()],
+        );
+        let result = FormattedNotification::new(fmt, &metadata, " * ").to_string();
+
+        assert_eq!(exp, result);
+    }
+
     #[test]
     fn test_formatted_notification_empty() {
         let fmt = "{album} {album_artists} {album_artist} {artists} {artist} {title} {track} {track_number} {nop} nop";
-        let exp = "       1 {nop} nop";
+        let exp = "        {nop} nop";
         let metadata = PlayerMetadata {
             track_id: Some("track-id".to_string()),
             album: None,
@@ -107,6 +834,7 @@ mod tests {
             title: None,
             track_number: None,
             track_url: None,
+            ..PlayerMetadata::default()
         };
         let join_str = " * ";
         let notification = FormattedNotification::new(fmt, &metadata, join_str);
@@ -114,4 +842,96 @@ mod tests {
 
         assert_eq!(exp, result);
     }
+
+    #[test]
+    fn test_formatted_notification_sections() {
+        let fmt = "{?album}{album} - {/album}{artist}{?nop} ({nop}){/nop}{?title} / {/album}";
+        let metadata = PlayerMetadata {
+            artists: Some(vec!["Simon & Garfunkel".to_string()]),
+            album: Some("".to_string()),
+            ..PlayerMetadata::default()
+        };
+        let result = FormattedNotification::new(fmt, &metadata, ", ").to_string();
+
+        // Empty values count as unset, and unclosed sections are kept as text
+        assert_eq!("Simon & Garfunkel{?title} / {/album}", result);
+
+        let metadata = PlayerMetadata {
+            album: Some("Bookends".to_string()),
+            ..metadata
+        };
+        let result = FormattedNotification::new(fmt, &metadata, ", ").to_string();
+        assert_eq!("Bookends - Simon & Garfunkel{?title} / {/album}", result);
+    }
+
+    #[test]
+    fn test_formatted_notification_filters() {
+        let fmt = r#"{album|Unknown album} {artist|join:" & "|upper} {title|truncate:10}
+                     {title|lower|"upper"} {genre|"|"} {nop|upper} {{title}} }"#;
+        let exp = "Unknown album SIMON & GARFUNKEL Mrs. Robi…
+                     mrs. robinson | {nop|upper} {title} }";
+        let metadata = PlayerMetadata {
+            artists: Some(vec!["Simon".to_string(), "Garfunkel".to_string()]),
+            title: Some("Mrs. Robinson".to_string()),
+            ..PlayerMetadata::default()
+        };
+        let result = FormattedNotification::new(fmt, &metadata, ", ").to_string();
+        assert_eq!(exp, result);
+
+        let result =
+            FormattedNotification::new("<b>{artist|escape}</b>", &metadata, " & ").to_string();
+        assert_eq!("<b>Simon &amp; Garfunkel</b>", result);
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        let (nodes, errors) = parse("{} { {/x} {?y}");
+        assert_eq!(
+            vec![
+                Node::Text("{} { {/x} ".to_string()),
+                Node::Text("{?y}".to_string())
+            ],
+            nodes
+        );
+        assert_eq!(
+            vec![
+                TemplateError::EmptyTag { column: 1 },
+                TemplateError::Unterminated { column: 4 },
+                TemplateError::UnmatchedSectionEnd {
+                    name: "x".to_string(),
+                    column: 6
+                },
+                TemplateError::UnclosedSection {
+                    name: "y".to_string(),
+                    column: 11
+                },
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(
+            Vec::<TemplateError>::new(),
+            validate(r#"{?album}{album|upper} - {/album}{artist|join:" & "} {meta:x}"#)
+        );
+        assert_eq!(
+            vec![
+                TemplateError::UnknownToken {
+                    token: "artsit".to_string(),
+                    column: 3
+                },
+                TemplateError::InvalidFilter {
+                    filter: "truncate:abc".to_string(),
+                    column: 12
+                },
+                TemplateError::UnknownToken {
+                    token: "nop".to_string(),
+                    column: 33
+                },
+            ],
+            validate("- {artsit}\n{title|truncate:abc} {?nop}{/nop}")
+        );
+    }
 }
@@ -1,16 +1,27 @@
 #[cfg(feature = "album-art")]
 mod art;
 
+mod active_player;
+mod ads;
 mod configuration;
 mod dbus;
+mod dedupe;
 mod formatter;
+mod media_type;
 mod mpris;
 mod notifier;
+mod pattern;
+mod player_filter;
+mod players;
+mod rewrite;
 mod signal_handler;
+mod stream;
+mod variant;
 
 use crate::configuration::{load_configuration, Configuration, ConfigurationError};
 use crate::dbus::{DBusConnection, DBusError};
 use crate::mpris::subscribe_mpris;
+use crate::notifier::subscribe_notifications;
 use crate::signal_handler::SignalHandler;
 use std::{thread, time::Duration};
 use thiserror::Error;
@@ -36,6 +47,8 @@ impl App {
     fn event_loop(&mut self) -> Result<(), AppError> {
         let mut dbus = DBusConnection::new()?;
         subscribe_mpris(&mut dbus)?;
+        subscribe_notifications(&mut dbus)?;
+        self.signal_handler.initialize(&mut dbus);
 
         loop {
             if let Err(err) = self.signal_handler.handle_pending(&mut dbus) {
@@ -43,7 +56,7 @@ impl App {
             }
             match dbus.next_signal() {
                 Ok(Some(signal)) => {
-                    if let Err(err) = self.signal_handler.handle_signal(signal) {
+                    if let Err(err) = self.signal_handler.handle_signal(signal, &mut dbus) {
                         log::error!("error handling signal: {:?}", err);
                     }
                 }
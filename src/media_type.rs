@@ -0,0 +1,167 @@
+use crate::mpris::{PlayerInfo, PlayerMetadata};
+use crate::stream::is_stream;
+use serde::{Deserialize, Serialize};
+
+// Heuristics classifying what is playing. Players don't say, so this relies
+// on the file extension of `xesam:url`, the genre, whether there is a
+// length, and which player it is.
+
+const VIDEO_EXTENSIONS: &[&str] = &[
+    "mp4", "m4v", "mkv", "webm", "avi", "mov", "wmv", "flv", "mpg", "mpeg", "ts", "ogv",
+];
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "mp3", "flac", "ogg", "oga", "opus", "m4a", "aac", "wav", "wma", "alac", "ape", "wv",
+];
+
+// Lowercased substrings of the identities of players which only play
+// podcasts, or of browsers, whose media is nearly always video.
+const PODCAST_PLAYERS: &[&str] = &["podcast", "gpodder", "kasts", "vocal"];
+const BROWSER_PLAYERS: &[&str] = &[
+    "firefox",
+    "chrom",
+    "brave",
+    "edge",
+    "opera",
+    "vivaldi",
+    "librewolf",
+    "epiphany",
+];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaType {
+    Music,
+    Podcast,
+    Video,
+    Stream,
+}
+
+/// Notification settings for one type of media. Formats and album art
+/// settings which aren't given fall back to the top-level settings.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct MediaSettings {
+    /// Show notifications for this type of media.
+    pub notify: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject_format: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_format: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_album_art: Option<bool>,
+}
+
+const DEFAULT_NOTIFY: bool = true;
+
+impl Default for MediaSettings {
+    fn default() -> Self {
+        Self {
+            notify: DEFAULT_NOTIFY,
+            subject_format: None,
+            body_format: None,
+            enable_album_art: None,
+        }
+    }
+}
+
+/// Guesses the type of media described by the metadata.
+pub fn classify(metadata: &PlayerMetadata, player: &PlayerInfo) -> MediaType {
+    let genre_is_podcast = metadata
+        .genres
+        .iter()
+        .flatten()
+        .any(|genre| genre.to_lowercase().contains("podcast"));
+    if genre_is_podcast {
+        return MediaType::Podcast;
+    }
+
+    match url_extension(metadata).as_deref() {
+        Some(ext) if VIDEO_EXTENSIONS.contains(&ext) => return MediaType::Video,
+        Some(ext) if AUDIO_EXTENSIONS.contains(&ext) => {
+            return if is_player(player, PODCAST_PLAYERS) {
+                MediaType::Podcast
+            } else {
+                MediaType::Music
+            };
+        }
+        _ => (),
+    }
+
+    if is_player(player, PODCAST_PLAYERS) {
+        MediaType::Podcast
+    } else if metadata.station.is_some() || is_stream(metadata, None) {
+        MediaType::Stream
+    } else if is_player(player, BROWSER_PLAYERS) {
+        MediaType::Video
+    } else {
+        MediaType::Music
+    }
+}
+
+// The lowercased extension of the file named by the URL, ignoring any query
+// string or fragment.
+fn url_extension(metadata: &PlayerMetadata) -> Option<String> {
+    let url = metadata.track_url.as_deref()?;
+    let path = url.split(['?', '#']).next()?;
+    let file_name = path.rsplit('/').next()?;
+    let (_, ext) = file_name.rsplit_once('.')?;
+    Some(ext.to_lowercase())
+}
+
+fn is_player(player: &PlayerInfo, names: &[&str]) -> bool {
+    [player.identity.as_deref(), player.bus_name.as_deref()]
+        .into_iter()
+        .flatten()
+        .map(str::to_lowercase)
+        .any(|name| names.iter().any(|n| name.contains(n)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify, MediaType};
+    use crate::mpris::{PlayerInfo, PlayerMetadata};
+
+    #[test]
+    fn test_classify() {
+        let mpv = PlayerInfo::default().with_identity("mpv");
+        let length = 240_000_000;
+
+        let song = PlayerMetadata::default()
+            .with_url("file:///music/Bookends/02%20Mrs.%20Robinson.FLAC")
+            .with_length(length);
+        assert_eq!(MediaType::Music, classify(&song, &mpv));
+        let gpodder = PlayerInfo::default().with_identity("gPodder");
+        assert_eq!(MediaType::Podcast, classify(&song, &gpodder));
+
+        let movie = PlayerMetadata::default()
+            .with_url("file:///videos/film.mkv?t=10")
+            .with_length(length);
+        assert_eq!(MediaType::Video, classify(&movie, &mpv));
+
+        let episode = PlayerMetadata {
+            genres: Some(vec!["Podcast".to_string()]),
+            ..PlayerMetadata::default()
+                .with_url("https://example.com/feed/episode-12.mp3")
+                .with_length(length)
+        };
+        assert_eq!(MediaType::Podcast, classify(&episode, &mpv));
+
+        let radio = PlayerMetadata::default().with_url("https://stream.radioparadise.com/mp3-192");
+        assert_eq!(MediaType::Stream, classify(&radio, &mpv));
+
+        let youtube = PlayerMetadata::default()
+            .with_url("https://www.youtube.com/watch?v=dQw4w9WgXcQ")
+            .with_length(length);
+        let firefox = PlayerInfo::default().with_identity("Mozilla Firefox");
+        assert_eq!(MediaType::Video, classify(&youtube, &firefox));
+
+        let spotify = PlayerInfo::default().with_identity("Spotify");
+        assert_eq!(
+            MediaType::Music,
+            classify(&PlayerMetadata::default(), &spotify)
+        );
+    }
+}
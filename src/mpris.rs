@@ -1,9 +1,16 @@
-use crate::dbus::{DBusConnection, DBusError};
+use crate::dbus::{subscribe_name_owner_changes, DBusConnection, DBusError, NameMatch};
+use crate::variant::{
+    coerce_bool, coerce_f64, coerce_i64, coerce_string, coerce_strings, render, signature,
+};
 use rustbus::message_builder::MarshalledMessage;
 use rustbus::wire::unmarshal::traits::Variant;
-use std::collections::HashMap;
+use rustbus::MessageBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::str::FromStr;
 
+const MPRIS_ROOT_INTERFACE: &str = "org.mpris.MediaPlayer2";
+pub const MPRIS_BUS_PREFIX: &str = "org.mpris.MediaPlayer2.";
 const MPRIS_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
 const MPRIS_SIGNAL_INTERFACE: &str = "org.freedesktop.DBus.Properties";
 const MPRIS_SIGNAL_MEMBER: &str = "PropertiesChanged";
@@ -13,9 +20,102 @@ const MPRIS_SIGNAL_OBJECT: &str = "/org/mpris/MediaPlayer2";
 pub struct MprisPropertiesChange {
     pub status: Option<PlayerStatus>,
     pub metadata: Option<PlayerMetadata>,
+    pub properties: PlayerProperties,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+// The remaining properties of the Player interface, see:
+// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct PlayerProperties {
+    pub loop_status: Option<LoopStatus>,
+    pub shuffle: Option<bool>,
+    /// Volume, where 1.0 is full volume.
+    pub volume: Option<f64>,
+    /// Playback rate, where 1.0 is normal speed.
+    pub rate: Option<f64>,
+    pub can_go_next: Option<bool>,
+    pub can_go_previous: Option<bool>,
+    pub can_play: Option<bool>,
+    pub can_pause: Option<bool>,
+    pub can_seek: Option<bool>,
+    pub can_control: Option<bool>,
+}
+
+impl PlayerProperties {
+    // Overwrites this state with the properties present in a change.
+    pub fn update(&mut self, change: &PlayerProperties) {
+        fn merge<T: Clone>(current: &mut Option<T>, change: &Option<T>) {
+            if change.is_some() {
+                current.clone_from(change);
+            }
+        }
+        merge(&mut self.loop_status, &change.loop_status);
+        merge(&mut self.shuffle, &change.shuffle);
+        merge(&mut self.volume, &change.volume);
+        merge(&mut self.rate, &change.rate);
+        merge(&mut self.can_go_next, &change.can_go_next);
+        merge(&mut self.can_go_previous, &change.can_go_previous);
+        merge(&mut self.can_play, &change.can_play);
+        merge(&mut self.can_pause, &change.can_pause);
+        merge(&mut self.can_seek, &change.can_seek);
+        merge(&mut self.can_control, &change.can_control);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LoopStatus {
+    None,
+    Track,
+    Playlist,
+}
+
+impl LoopStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoopStatus::None => "None",
+            LoopStatus::Track => "Track",
+            LoopStatus::Playlist => "Playlist",
+        }
+    }
+}
+
+impl FromStr for LoopStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "None" => Ok(LoopStatus::None),
+            "Track" => Ok(LoopStatus::Track),
+            "Playlist" => Ok(LoopStatus::Playlist),
+            _ => Err(()),
+        }
+    }
+}
+
+// Properties of the MPRIS root interface describing the player itself, see:
+// https://specifications.freedesktop.org/mpris-spec/latest/Media_Player.html
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct PlayerInfo {
+    /// The well-known bus name, e.g. `org.mpris.MediaPlayer2.spotify`.
+    pub bus_name: Option<String>,
+    pub identity: Option<String>,
+    pub desktop_entry: Option<String>,
+}
+
+impl PlayerInfo {
+    // A human-readable name for the player: its identity, falling back to
+    // the bus name without the `org.mpris.MediaPlayer2.` prefix.
+    pub fn name(&self) -> Option<&str> {
+        self.identity.as_deref().or_else(|| {
+            self.bus_name
+                .as_deref()
+                .map(|name| name.strip_prefix(MPRIS_BUS_PREFIX).unwrap_or(name))
+        })
+    }
+}
+
+// See: https://www.freedesktop.org/wiki/Specifications/mpris-spec/metadata/
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct PlayerMetadata {
     pub track_id: Option<String>,
     pub album: Option<String>,
@@ -25,6 +125,151 @@ pub struct PlayerMetadata {
     pub title: Option<String>,
     pub track_number: Option<u32>,
     pub track_url: Option<String>,
+
+    /// Track length, in microseconds.
+    pub length: Option<i64>,
+    pub genres: Option<Vec<String>>,
+    pub disc_number: Option<i32>,
+    pub composers: Option<Vec<String>>,
+    pub lyricists: Option<Vec<String>>,
+    pub comments: Option<Vec<String>>,
+    /// Date the track was created, as an ISO 8601 date (often just a year).
+    pub content_created: Option<String>,
+    /// User-specified rating, from 0.0 to 1.0.
+    pub user_rating: Option<f64>,
+    pub use_count: Option<i32>,
+    pub audio_bpm: Option<i32>,
+    pub musicbrainz_track_id: Option<String>,
+    pub musicbrainz_album_id: Option<String>,
+    pub musicbrainz_artist_ids: Option<Vec<String>>,
+    pub musicbrainz_album_artist_ids: Option<Vec<String>>,
+
+    /// Name of the internet radio station, for streams which were
+    /// recognized as such (see `stream.rs`).
+    pub station: Option<String>,
+
+    /// Every other key of the metadata map (e.g. `xesam:asText`, or
+    /// player-specific keys such as `mpv:*`), with values rendered as
+    /// strings.
+    pub extra: BTreeMap<String, Vec<String>>,
+}
+
+impl PlayerMetadata {
+    // Whether two metadata snapshots describe the same track. Some players
+    // reuse a single track ID for every track, so the basic tags are
+    // compared as well.
+    pub fn is_same_track(&self, other: &PlayerMetadata) -> bool {
+        self.track_id == other.track_id
+            && self.title == other.title
+            && self.artists == other.artists
+            && self.album == other.album
+    }
+
+    // Whether the metadata names an artist. Players without artist
+    // information sometimes send a list of blank strings (e.g. `[""]`)
+    // rather than leaving the key out.
+    pub fn has_artist(&self) -> bool {
+        self.artists
+            .iter()
+            .flatten()
+            .any(|artist| !artist.trim().is_empty())
+    }
+}
+
+// Builders for the players and tracks used by the tests of every module.
+#[cfg(test)]
+impl PlayerInfo {
+    pub fn with_bus_name(mut self, bus_name: &str) -> Self {
+        self.bus_name = Some(bus_name.to_string());
+        self
+    }
+
+    pub fn with_identity(mut self, identity: &str) -> Self {
+        self.identity = Some(identity.to_string());
+        self
+    }
+}
+
+#[cfg(test)]
+impl PlayerMetadata {
+    pub fn with_track_id(mut self, track_id: &str) -> Self {
+        self.track_id = Some(track_id.to_string());
+        self
+    }
+
+    pub fn with_title(mut self, title: &str) -> Self {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    pub fn with_artists(mut self, artists: &[&str]) -> Self {
+        self.artists = Some(artists.iter().map(|a| a.to_string()).collect());
+        self
+    }
+
+    pub fn with_album(mut self, album: &str) -> Self {
+        self.album = Some(album.to_string());
+        self
+    }
+
+    pub fn with_url(mut self, url: &str) -> Self {
+        self.track_url = Some(url.to_string());
+        self
+    }
+
+    pub fn with_length(mut self, length: i64) -> Self {
+        self.length = Some(length);
+        self
+    }
+}
+
+// Transport controls which can be invoked on a player, see:
+// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayerAction {
+    Previous,
+    PlayPause,
+    Next,
+}
+
+impl PlayerAction {
+    pub fn key(&self) -> &'static str {
+        match self {
+            PlayerAction::Previous => "previous",
+            PlayerAction::PlayPause => "play_pause",
+            PlayerAction::Next => "next",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PlayerAction::Previous => "Previous",
+            PlayerAction::PlayPause => "Play/Pause",
+            PlayerAction::Next => "Next",
+        }
+    }
+
+    fn method(&self) -> &'static str {
+        match self {
+            PlayerAction::Previous => "Previous",
+            PlayerAction::PlayPause => "PlayPause",
+            PlayerAction::Next => "Next",
+        }
+    }
+}
+
+impl FromStr for PlayerAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "previous" => Ok(PlayerAction::Previous),
+            "play_pause" => Ok(PlayerAction::PlayPause),
+            "next" => Ok(PlayerAction::Next),
+            _ => Err(()),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -61,37 +306,247 @@ impl TryFrom<MarshalledMessage> for MprisPropertiesChange {
 
         // HashMap<String, Variant>
         let outer: HashMap<String, Variant> = parser.get()?;
+        let sender = message.dynheader.sender.as_deref().unwrap_or_default();
+        Ok(Self::from_properties(&outer, sender))
+    }
+}
+
+impl MprisPropertiesChange {
+    fn from_properties(outer: &HashMap<String, Variant>, sender: &str) -> Self {
         let metadata_map: Option<HashMap<String, Variant>> =
             outer.get("Metadata").and_then(|m| m.get().ok());
         let status: Option<PlayerStatus> = outer
             .get("PlaybackStatus")
-            .and_then(|s| s.get().ok())
-            .and_then(|s| PlayerStatus::from_str(s).ok());
-        let metadata: Option<PlayerMetadata> = metadata_map.map(|m| metadata_from_map(&m));
+            .and_then(coerce_string)
+            .and_then(|s| PlayerStatus::from_str(&s).ok());
+        let metadata: Option<PlayerMetadata> = metadata_map.map(|m| metadata_from_map(&m, sender));
+        let properties = PlayerProperties {
+            loop_status: outer
+                .get("LoopStatus")
+                .and_then(coerce_string)
+                .and_then(|s| LoopStatus::from_str(&s).ok()),
+            shuffle: outer.get("Shuffle").and_then(coerce_bool),
+            volume: outer.get("Volume").and_then(coerce_f64),
+            rate: outer.get("Rate").and_then(coerce_f64),
+            can_go_next: outer.get("CanGoNext").and_then(coerce_bool),
+            can_go_previous: outer.get("CanGoPrevious").and_then(coerce_bool),
+            can_play: outer.get("CanPlay").and_then(coerce_bool),
+            can_pause: outer.get("CanPause").and_then(coerce_bool),
+            can_seek: outer.get("CanSeek").and_then(coerce_bool),
+            can_control: outer.get("CanControl").and_then(coerce_bool),
+        };
 
-        Ok(Self { status, metadata })
+        Self {
+            status,
+            metadata,
+            properties,
+        }
     }
 }
 
-fn metadata_from_map(inner: &HashMap<String, Variant>) -> PlayerMetadata {
-    PlayerMetadata {
-        track_id: inner.get("mpris:trackid").and_then(|x| x.get().ok()),
-        album: inner.get("xesam:album").and_then(|x| x.get().ok()),
-        album_artists: inner.get("xesam:albumArtist").and_then(|x| x.get().ok()),
-        art_url: inner.get("mpris:artUrl").and_then(|x| x.get().ok()),
-        artists: inner.get("xesam:artist").and_then(|x| x.get().ok()),
-        title: inner.get("xesam:title").and_then(|x| x.get().ok()),
-        track_number: inner.get("xesam:trackNumber").and_then(|x| x.get().ok()),
-        track_url: inner.get("xesam:url").and_then(|x| x.get().ok()),
+// Reads metadata values leniently, noting values whose type differs from the
+// one given by the specification.
+struct MetadataReader<'a, 'fds, 'buf> {
+    map: &'a HashMap<String, Variant<'fds, 'buf>>,
+    read_keys: HashSet<&'static str>,
+    non_conforming: Vec<String>,
+}
+
+impl MetadataReader<'_, '_, '_> {
+    fn read<T>(
+        &mut self,
+        key: &'static str,
+        expected: &str,
+        coerce: fn(&Variant) -> Option<T>,
+    ) -> Option<T> {
+        self.read_keys.insert(key);
+        let value = self.map.get(key)?;
+        let sig = signature(value);
+        if sig != expected {
+            self.non_conforming.push(format!("{key} ({sig})"));
+        }
+        coerce(value)
     }
+
+    fn string(&mut self, key: &'static str) -> Option<String> {
+        self.read(key, "s", coerce_string)
+    }
+
+    fn strings(&mut self, key: &'static str) -> Option<Vec<String>> {
+        self.read(key, "as", coerce_strings)
+    }
+
+    fn int<T: TryFrom<i64>>(&mut self, key: &'static str, expected: &str) -> Option<T> {
+        self.read(key, expected, coerce_i64)
+            .and_then(|i| T::try_from(i).ok())
+    }
+
+    // Renders every key which hasn't been read as a known field.
+    fn remaining(&self) -> BTreeMap<String, Vec<String>> {
+        self.map
+            .iter()
+            .filter(|(key, _)| !self.read_keys.contains(key.as_str()))
+            .filter_map(|(key, value)| render(value).map(|values| (key.clone(), values)))
+            .collect()
+    }
+}
+
+fn metadata_from_map(inner: &HashMap<String, Variant>, sender: &str) -> PlayerMetadata {
+    let mut reader = MetadataReader {
+        map: inner,
+        read_keys: HashSet::new(),
+        non_conforming: Vec::new(),
+    };
+    let mut metadata = PlayerMetadata {
+        track_id: reader.read("mpris:trackid", "o", coerce_string),
+        album: reader.string("xesam:album"),
+        album_artists: reader.strings("xesam:albumArtist"),
+        art_url: reader.string("mpris:artUrl"),
+        artists: reader.strings("xesam:artist"),
+        title: reader.string("xesam:title"),
+        track_number: reader.int("xesam:trackNumber", "i"),
+        track_url: reader.string("xesam:url"),
+        length: reader.int("mpris:length", "x"),
+        genres: reader.strings("xesam:genre"),
+        disc_number: reader.int("xesam:discNumber", "i"),
+        composers: reader.strings("xesam:composer"),
+        lyricists: reader.strings("xesam:lyricist"),
+        comments: reader.strings("xesam:comment"),
+        content_created: reader.string("xesam:contentCreated"),
+        user_rating: reader.read("xesam:userRating", "d", coerce_f64),
+        use_count: reader.int("xesam:useCount", "i"),
+        audio_bpm: reader.int("xesam:audioBPM", "i"),
+        musicbrainz_track_id: reader.string("xesam:musicBrainzTrackID"),
+        musicbrainz_album_id: reader.string("xesam:musicBrainzAlbumID"),
+        musicbrainz_artist_ids: reader.strings("xesam:musicBrainzArtistID"),
+        musicbrainz_album_artist_ids: reader.strings("xesam:musicBrainzAlbumArtistID"),
+        station: None,
+        extra: BTreeMap::new(),
+    };
+    metadata.extra = reader.remaining();
+
+    if !reader.non_conforming.is_empty() {
+        log::debug!(
+            "Player {} sent metadata with non-conforming types: {}",
+            sender,
+            reader.non_conforming.join(", ")
+        );
+    }
+    metadata
+}
+
+// Whether the given bus name is an MPRIS player's well-known name.
+pub fn is_player_bus_name(name: &str) -> bool {
+    name.starts_with(MPRIS_BUS_PREFIX)
+}
+
+// Lists the well-known bus names of every running MPRIS player.
+pub fn list_player_names(dbus: &mut DBusConnection) -> Result<Vec<String>, DBusError> {
+    Ok(dbus
+        .list_names()?
+        .into_iter()
+        .filter(|name| is_player_bus_name(name))
+        .collect())
+}
+
+// Fetches the root interface properties of the player owning the
+// `destination` bus name.
+pub fn fetch_player_info(
+    dbus: &mut DBusConnection,
+    destination: &str,
+) -> Result<PlayerInfo, DBusError> {
+    let reply = dbus.get_all_properties(destination, MPRIS_SIGNAL_OBJECT, MPRIS_ROOT_INTERFACE)?;
+    let properties: HashMap<String, Variant> = reply.body.parser().get()?;
+    Ok(PlayerInfo {
+        bus_name: None,
+        identity: properties.get("Identity").and_then(coerce_string),
+        desktop_entry: properties.get("DesktopEntry").and_then(coerce_string),
+    })
+}
+
+// Fetches the current state of the player owning the `destination` bus name,
+// in the same form as a change signal carrying every property.
+pub fn fetch_player_properties(
+    dbus: &mut DBusConnection,
+    destination: &str,
+) -> Result<MprisPropertiesChange, DBusError> {
+    let reply = dbus.get_all_properties(destination, MPRIS_SIGNAL_OBJECT, MPRIS_INTERFACE)?;
+    let properties: HashMap<String, Variant> = reply.body.parser().get()?;
+    Ok(MprisPropertiesChange::from_properties(
+        &properties,
+        destination,
+    ))
+}
+
+// Fetches the playback position, in microseconds, of the player owning the
+// `destination` bus name. `Position` never appears in change signals, so it
+// has to be queried whenever it's needed.
+pub fn fetch_position(dbus: &mut DBusConnection, destination: &str) -> Result<i64, DBusError> {
+    let reply = dbus.get_property(
+        destination,
+        MPRIS_SIGNAL_OBJECT,
+        MPRIS_INTERFACE,
+        "Position",
+    )?;
+    let position: Variant = reply.body.parser().get()?;
+    coerce_i64(&position).ok_or_else(|| {
+        DBusError::Invalid(format!(
+            "Position of {} has type {}",
+            destination,
+            signature(&position)
+        ))
+    })
+}
+
+// Calls the transport control method for the given action on the player
+// owning the `destination` bus name. The call is fire-and-forget.
+pub fn call_player_action(
+    dbus: &mut DBusConnection,
+    destination: &str,
+    action: PlayerAction,
+) -> Result<(), DBusError> {
+    let message = MessageBuilder::new()
+        .call(action.method())
+        .at(destination)
+        .on(MPRIS_SIGNAL_OBJECT)
+        .with_interface(MPRIS_INTERFACE)
+        .build();
+    dbus.send_message(&message)
 }
 
 // Convenience method to subscribe a DBusConnection to MPRIS player property
-// change events (e.g., track changes).
+// change events (e.g., track changes), and to players starting and exiting.
 pub fn subscribe_mpris(dbus: &mut DBusConnection) -> Result<(), DBusError> {
     dbus.subscribe(
         MPRIS_SIGNAL_INTERFACE,
         MPRIS_SIGNAL_MEMBER,
         MPRIS_SIGNAL_OBJECT,
-    )
+    )?;
+    subscribe_name_owner_changes(dbus, NameMatch::Namespace(MPRIS_ROOT_INTERFACE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PlayerMetadata;
+
+    #[test]
+    fn test_is_same_track() {
+        let track = PlayerMetadata {
+            track_id: Some("/org/mpris/MediaPlayer2/Track/1".to_string()),
+            title: Some("The Boxer".to_string()),
+            artists: Some(vec!["Simon & Garfunkel".to_string()]),
+            ..PlayerMetadata::default()
+        };
+        let refreshed = PlayerMetadata {
+            art_url: Some("https://example.com/art.jpg".to_string()),
+            ..track.clone()
+        };
+        let next = PlayerMetadata {
+            title: Some("Cecilia".to_string()),
+            ..track.clone()
+        };
+
+        assert!(track.is_same_track(&refreshed));
+        assert!(!track.is_same_track(&next));
+    }
 }
@@ -1,45 +1,223 @@
 #[cfg(feature = "album-art")]
 use image::DynamicImage;
 
-use crate::dbus::{DBusConnection, DBusError};
-use crate::formatter::FormattedNotification;
-use crate::mpris::PlayerMetadata;
+use crate::dbus::{
+    subscribe_name_owner_changes, DBusConnection, DBusError, NameMatch, NameOwnerChange,
+};
+use crate::formatter::{references, FormattedNotification};
+use crate::mpris::{fetch_position, PlayerAction, PlayerInfo, PlayerMetadata, PlayerProperties};
 use crate::Configuration;
+use rustbus::message_builder::MarshalledMessage;
 use rustbus::MessageBuilder;
 use rustbus::{dbus_variant_sig, Marshal, Signature, Unmarshal};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::str::FromStr;
 use std::time::Instant;
 
 const NOTIFICATION_NAMESPACE: &str = "org.freedesktop.Notifications";
 const NOTIFICATION_OBJECTPATH: &str = "/org/freedesktop/Notifications";
 const NOTIFICATION_SOURCE: &str = "mpris-notifier";
+const NOTIFICATION_ACTION_MEMBER: &str = "ActionInvoked";
+const NOTIFICATION_CLOSE_MEMBER: &str = "CloseNotification";
+const NOTIFICATION_FILE_SCHEME: &str = "file://";
+
+// The latest version of the notification specification.
+const CURRENT_SPEC_VERSION: (u32, u32) = (1, 2);
+
+// Tokens which depend on the playback position.
+const POSITION_TOKENS: &[&str] = &["position", "remaining"];
+
+// Action keys are namespaced and carry the D-Bus sender of the player which
+// produced the notification, e.g. `mpris-notifier:next::1.42`, so that an
+// `ActionInvoked` signal can be routed back to the right player.
+const ACTION_KEY_PREFIX: &str = "mpris-notifier";
+
+// Property change notifications replace each other, but not the player's
+// track notification. Their slot is keyed by the sender with this suffix,
+// which can't occur in a bus name.
+const PROPERTY_SLOT_SUFFIX: &str = "/properties";
+
+/// Determines which earlier notification, if any, a new notification
+/// replaces on screen.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplacePolicy {
+    /// Each player replaces its own previous notification.
+    PerPlayer,
+    /// Every notification replaces the previous one, regardless of player.
+    Global,
+    /// Notifications are never replaced, and stack up.
+    Stack,
+}
+
+/// Whether notification bodies are sent as markup, in which case metadata
+/// substituted into the body format is escaped.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkupMode {
+    /// Markup is used if the notification server advertises `body-markup`.
+    Auto,
+    /// Markup is always used.
+    Always,
+    /// Bodies are always sent as plain text.
+    Never,
+}
+
+/// Identity and capabilities of the running notification server, see:
+/// https://specifications.freedesktop.org/notification-spec/notification-spec-latest.html#protocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerInformation {
+    pub name: String,
+    pub vendor: String,
+    pub version: String,
+    pub spec_version: String,
+    pub capabilities: Vec<String>,
+}
+
+impl ServerInformation {
+    fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    // Parses the "major.minor" specification version, treating anything
+    // unparseable as the current version, as for an unknown server.
+    fn spec_version(&self) -> (u32, u32) {
+        let mut parts = self.spec_version.split('.').map(|p| p.parse().ok());
+        match (parts.next().flatten(), parts.next().flatten()) {
+            (Some(major), Some(minor)) => (major, minor),
+            (Some(major), None) => (major, 0),
+            _ => CURRENT_SPEC_VERSION,
+        }
+    }
+
+    // The image hint names were renamed between specification versions.
+    fn image_data_hint(&self) -> &'static str {
+        match self.spec_version() {
+            (1, 0) => "icon_data",
+            (1, 1) => "image_data",
+            _ => "image-data",
+        }
+    }
+
+    fn image_path_hint(&self) -> Option<&'static str> {
+        match self.spec_version() {
+            (1, 0) => None,
+            (1, 1) => Some("image_path"),
+            _ => Some("image-path"),
+        }
+    }
+}
 
 pub struct Notifier {
     configuration: Configuration,
+
+    // Information about the notification server, if it could be queried.
+    // When unknown, notifications are sent as if every feature is supported,
+    // except body markup.
+    server: Option<ServerInformation>,
+
+    // Unique bus name of the notification server, the only connection whose
+    // `ActionInvoked` signals are trusted.
+    server_owner: Option<String>,
+
+    // Map from <Slot> -> <ID of the last notification sent for it>, where
+    // the slot is the D-Bus sender, or its property change slot.
+    notification_ids: HashMap<String, u32>,
+
+    // ID of the last notification sent for any sender.
+    last_id: Option<u32>,
 }
 
 #[derive(Debug)]
 pub struct Notification {
     sender: String,
+    player: PlayerInfo,
     metadata: PlayerMetadata,
     album_art: Option<NotificationImage>,
+    properties: PlayerProperties,
+
+    // Formats overriding the configured subject and body formats, used by
+    // property change notifications.
+    formats: Option<(String, String)>,
+
+    // Progress value (0-100) shown by servers supporting the `value` hint.
+    value: Option<i32>,
+
+    // Name of the active player, which may differ from `player`.
+    active_player: Option<String>,
+
+    // Overrides the configured `enable_album_art`.
+    album_art_enabled: Option<bool>,
+
+    // Whether this reports a property change (e.g. volume) rather than a
+    // track, which replaces its own notification and has no actions.
+    property_change: bool,
     last_touched: Instant,
 }
 
 impl Notification {
     pub fn new(
         sender: &str,
+        player: &PlayerInfo,
         metadata: &PlayerMetadata,
         album_art: Option<NotificationImage>,
     ) -> Self {
         Self {
             sender: sender.to_string(),
+            player: player.clone(),
             metadata: metadata.clone(),
             album_art,
+            properties: PlayerProperties::default(),
+            formats: None,
+            value: None,
+            active_player: None,
+            album_art_enabled: None,
+            property_change: false,
             last_touched: Instant::now(),
         }
     }
 
+    /// Sets the playback properties available to property tokens.
+    pub fn with_properties(mut self, properties: &PlayerProperties) -> Self {
+        self.properties = properties.clone();
+        self
+    }
+
+    /// Overrides the configured subject and body formats.
+    pub fn with_formats(mut self, subject_format: &str, body_format: &str) -> Self {
+        self.formats = Some((subject_format.to_string(), body_format.to_string()));
+        self
+    }
+
+    /// Overrides whether album art is shown.
+    pub fn with_album_art_enabled(mut self, enabled: Option<bool>) -> Self {
+        self.album_art_enabled = enabled;
+        self
+    }
+
+    pub fn album_art_enabled(&self) -> Option<bool> {
+        self.album_art_enabled
+    }
+
+    /// Sets the name of the active player, for the `{player}` token.
+    pub fn with_active_player(mut self, name: Option<&str>) -> Self {
+        self.active_player = name.map(str::to_string);
+        self
+    }
+
+    /// Marks the notification as reporting a property change.
+    pub fn for_property_change(mut self) -> Self {
+        self.property_change = true;
+        self
+    }
+
+    /// Sets the `value` hint, rendered as a progress bar by some servers.
+    pub fn with_value(mut self, value: i32) -> Self {
+        self.value = Some(value);
+        self
+    }
+
     // Updates an existing notification with new metadata or album art.
     pub fn update(&mut self, metadata: &PlayerMetadata, album_art: Option<NotificationImage>) {
         self.metadata = metadata.clone();
@@ -51,13 +229,21 @@ impl Notification {
         &self.sender
     }
 
+    pub fn metadata(&self) -> &PlayerMetadata {
+        &self.metadata
+    }
+
+    pub fn player(&self) -> &PlayerInfo {
+        &self.player
+    }
+
     pub fn last_touched(&self) -> Instant {
         self.last_touched
     }
 }
 
 type NotificationHintMap = HashMap<String, NotificationHintVariant>;
-dbus_variant_sig!(NotificationHintVariant, CaseString => String; CaseNotificationImage => NotificationImage);
+dbus_variant_sig!(NotificationHintVariant, CaseString => String; CaseInt => i32; CaseNotificationImage => NotificationImage);
 
 // See: https://specifications.freedesktop.org/notification-spec/notification-spec-latest.html#icons-and-images
 #[derive(Marshal, Unmarshal, Signature, Debug, Eq, PartialEq, Clone)]
@@ -93,58 +279,534 @@ impl Notifier {
     pub fn new(configuration: &Configuration) -> Self {
         Self {
             configuration: configuration.clone(),
+            server: None,
+            server_owner: None,
+            notification_ids: HashMap::new(),
+            last_id: None,
         }
     }
 
     pub fn send_notification(
-        &self,
+        &mut self,
         notification: Notification,
         dbus: &mut DBusConnection,
     ) -> Result<(), DBusError> {
         let metadata = &notification.metadata;
-        let album_art = notification.album_art;
 
         // See: https://github.com/hoodie/notify-rust/blob/main/src/xdg/dbus_rs.rs#L64-L73
-        let mut message = MessageBuilder::new()
-            .call("Notify")
-            .at(NOTIFICATION_NAMESPACE)
-            .on(NOTIFICATION_OBJECTPATH)
-            .with_interface(NOTIFICATION_NAMESPACE)
-            .build();
+        let mut message = self.server_call("Notify");
 
-        let subject = self.format_metadata(&self.configuration.subject_format, metadata);
-        let body = self.format_metadata(&self.configuration.body_format, metadata);
+        let (subject_format, body_format) = match &notification.formats {
+            Some((subject, body)) => (subject.as_str(), body.as_str()),
+            None => (
+                self.configuration.subject_format.as_str(),
+                self.configuration.body_format.as_str(),
+            ),
+        };
+        // The position changes continuously, so it's only queried when the
+        // formats show it.
+        let position = if references(subject_format, POSITION_TOKENS)
+            || references(body_format, POSITION_TOKENS)
+        {
+            fetch_position(dbus, &notification.sender)
+                .map_err(|err| {
+                    log::debug!(
+                        "Unable to query position of {}: {:?}",
+                        notification.sender,
+                        err
+                    )
+                })
+                .ok()
+        } else {
+            None
+        };
+
+        // The summary is never parsed as markup, only the body.
+        let subject = self.format_metadata(subject_format, &notification, position, false);
+        let body = self.format_metadata(body_format, &notification, position, self.body_markup());
 
         if subject.trim().is_empty() && body.trim().is_empty() {
             // Don't bother popping an empty notification window up
             return Ok(());
         }
 
-        message.body.push_param(NOTIFICATION_SOURCE)?; // appname (TODO)
-        message.body.push_param(0_u32)?; // update ID
-        message.body.push_param("")?; // icon
+        let art_enabled = notification
+            .album_art_enabled
+            .unwrap_or(self.configuration.enable_album_art);
+        let album_art = notification.album_art.filter(|_| art_enabled);
+        let art_path_hint = metadata
+            .art_url
+            .as_ref()
+            .and_then(|url| self.image_path_hint(url))
+            .filter(|_| art_enabled);
+        let player = &notification.player;
+        let app_name = player.identity.as_deref().unwrap_or(NOTIFICATION_SOURCE);
+        let has_art = album_art.is_some() || art_path_hint.is_some();
+        let icon = match (&player.desktop_entry, has_art) {
+            (Some(desktop_entry), false) => desktop_entry.as_str(),
+            _ => "",
+        };
+
+        let slot = if notification.property_change {
+            property_slot(&notification.sender)
+        } else {
+            notification.sender.clone()
+        };
+        message.body.push_param(app_name)?; // appname
+        message.body.push_param(self.replaces_id(&slot))?; // update ID
+        message.body.push_param(icon)?; // icon
         message.body.push_param(subject)?; // summary
         message.body.push_param(body)?; // body
-        message.body.push_param(Vec::<String>::new())?; // actions (array of strings)
+        let actions = if self.supports("actions", true) && !notification.property_change {
+            self.actions(&notification.sender)
+        } else {
+            Vec::new()
+        };
+        message.body.push_param(actions)?; // actions (array of strings)
         let mut hints: NotificationHintMap = HashMap::new();
-        hints.insert(
-            "x-canonical-private-synchronous".to_string(),
-            NotificationHintVariant::CaseString(NOTIFICATION_SOURCE.to_string()),
-        );
+        if let Some(key) = self.synchronous_key(&slot) {
+            hints.insert(
+                "x-canonical-private-synchronous".to_string(),
+                NotificationHintVariant::CaseString(key),
+            );
+        }
+        if let Some(desktop_entry) = &player.desktop_entry {
+            hints.insert(
+                "desktop-entry".to_string(),
+                NotificationHintVariant::CaseString(desktop_entry.to_string()),
+            );
+        }
         if let Some(album_art) = album_art {
             hints.insert(
-                "image-data".to_string(),
+                self.image_data_hint().to_string(),
                 NotificationHintVariant::CaseNotificationImage(album_art),
             );
+        } else if let (Some(art_url), Some(hint)) = (metadata.art_url.as_ref(), art_path_hint) {
+            hints.insert(
+                hint.to_string(),
+                NotificationHintVariant::CaseString(art_url.to_string()),
+            );
+        }
+        if let Some(value) = notification.value {
+            hints.insert(
+                "value".to_string(),
+                NotificationHintVariant::CaseInt(value.clamp(0, 100)),
+            );
         }
         message.body.push_param(&hints)?; // hints (dict of a{sv})
         message.body.push_param(-1_i32)?; // timeout
 
+        let reply = dbus.call(&message)?;
+        let id: u32 = reply.body.parser().get()?;
+        self.remember_notification(slot, id);
+
+        Ok(())
+    }
+
+    /// Queries the notification server's identity and capabilities, which
+    /// determine the features used by subsequent notifications.
+    pub fn refresh_server_information(
+        &mut self,
+        dbus: &mut DBusConnection,
+    ) -> Result<(), DBusError> {
+        self.server = None;
+        self.server_owner = Some(dbus.get_name_owner(NOTIFICATION_NAMESPACE)?);
+
+        let message = self.server_call("GetCapabilities");
+        let capabilities: Vec<String> = dbus.call(&message)?.body.parser().get()?;
+
+        let message = self.server_call("GetServerInformation");
+        let reply = dbus.call(&message)?;
+        let (name, vendor, version, spec_version): (String, String, String, String) =
+            reply.body.parser().get4()?;
+
+        let server = ServerInformation {
+            name,
+            vendor,
+            version,
+            spec_version,
+            capabilities,
+        };
+        log::info!(
+            "Notification server: {} {} (spec {}), capabilities: {}",
+            server.name,
+            server.version,
+            server.spec_version,
+            server.capabilities.join(", ")
+        );
+        self.server = Some(server);
+        Ok(())
+    }
+
+    /// Re-queries the notification server when it is replaced on the bus.
+    /// Notifications sent to a previous server can no longer be replaced or
+    /// closed.
+    pub fn handle_name_owner_change(
+        &mut self,
+        change: &NameOwnerChange,
+        dbus: &mut DBusConnection,
+    ) -> Result<(), DBusError> {
+        if change.name != NOTIFICATION_NAMESPACE {
+            return Ok(());
+        }
+
+        self.server = None;
+        self.server_owner = None;
+        self.notification_ids.clear();
+        self.last_id = None;
+        if change.new_owner.is_empty() {
+            return Ok(());
+        }
+        self.refresh_server_information(dbus)
+    }
+
+    /// Parses an `ActionInvoked` signal for one of our notifications. Any
+    /// connection can emit the signal, so it is only accepted from the
+    /// current notification server and for a notification we sent.
+    pub fn invoked_action(
+        &self,
+        signal: &MarshalledMessage,
+    ) -> Result<Option<InvokedAction>, DBusError> {
+        let invoked = match InvokedAction::from_signal(signal)? {
+            Some(invoked) => invoked,
+            None => return Ok(None),
+        };
+        if !self.accepts_action(invoked.id, signal.dynheader.sender.as_deref()) {
+            log::debug!(
+                "Ignoring action for notification {} from {:?}",
+                invoked.id,
+                signal.dynheader.sender
+            );
+            return Ok(None);
+        }
+        Ok(Some(invoked))
+    }
+
+    fn accepts_action(&self, id: u32, emitter: Option<&str>) -> bool {
+        emitter.is_some()
+            && emitter == self.server_owner.as_deref()
+            && self.notification_ids.values().any(|sent| *sent == id)
+    }
+
+    /// Whether album art at the given URL should be fetched and sent as
+    /// image data. Local art is sent by path instead, where the server
+    /// supports it.
+    pub fn wants_image_data(&self, art_url: &str) -> bool {
+        self.image_path_hint(art_url).is_none()
+    }
+
+    fn body_markup(&self) -> bool {
+        match self.configuration.body_markup {
+            MarkupMode::Auto => self.supports("body-markup", false),
+            MarkupMode::Always => true,
+            MarkupMode::Never => false,
+        }
+    }
+
+    fn supports(&self, capability: &str, default: bool) -> bool {
+        self.server
+            .as_ref()
+            .map(|server| server.has_capability(capability))
+            .unwrap_or(default)
+    }
+
+    fn image_data_hint(&self) -> &'static str {
+        self.server
+            .as_ref()
+            .map(|server| server.image_data_hint())
+            .unwrap_or("image-data")
+    }
+
+    fn image_path_hint(&self, art_url: &str) -> Option<&'static str> {
+        if !art_url.starts_with(NOTIFICATION_FILE_SCHEME) {
+            return None;
+        }
+        self.server
+            .as_ref()
+            .and_then(|server| server.image_path_hint())
+    }
+
+    fn server_call(&self, member: &str) -> MarshalledMessage {
+        MessageBuilder::new()
+            .call(member)
+            .at(NOTIFICATION_NAMESPACE)
+            .on(NOTIFICATION_OBJECTPATH)
+            .with_interface(NOTIFICATION_NAMESPACE)
+            .build()
+    }
+
+    // Closes the last notification sent for the given sender, if it is
+    // still known.
+    pub fn close_notification(
+        &mut self,
+        sender: &str,
+        dbus: &mut DBusConnection,
+    ) -> Result<(), DBusError> {
+        let id = match self.take_notification(sender) {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+
+        let mut message = self.server_call(NOTIFICATION_CLOSE_MEMBER);
+        message.body.push_param(id)?;
         dbus.send_message(&message)
     }
 
+    /// Forgets the notification of a sender which has gone away, without
+    /// closing it.
+    pub fn forget_notification(&mut self, sender: &str) {
+        self.notification_ids.remove(sender);
+        self.notification_ids.remove(&property_slot(sender));
+    }
+
+    // Records the ID the server assigned to the notification in the given
+    // slot.
+    fn remember_notification(&mut self, slot: String, id: u32) {
+        // A replaced notification no longer belongs to its previous slot.
+        self.notification_ids.retain(|_, existing| *existing != id);
+        self.notification_ids.insert(slot, id);
+        self.last_id = Some(id);
+    }
+
+    // Removes the ID of the sender's notification, so that it is no longer
+    // replaced, and returns it.
+    fn take_notification(&mut self, sender: &str) -> Option<u32> {
+        let id = self.notification_ids.remove(sender)?;
+        if self.last_id == Some(id) {
+            self.last_id = None;
+        }
+        Some(id)
+    }
+
+    // The ID of the notification to be replaced by a new notification in the
+    // given slot, where 0 requests a new notification.
+    fn replaces_id(&self, slot: &str) -> u32 {
+        let id = match self.configuration.replace_policy {
+            ReplacePolicy::PerPlayer => self.notification_ids.get(slot).copied(),
+            ReplacePolicy::Global => self.last_id,
+            ReplacePolicy::Stack => None,
+        };
+        id.unwrap_or(0)
+    }
+
+    // Servers such as dunst and notify-osd replace notifications which share
+    // the same synchronous key, so the key follows the replace policy.
+    fn synchronous_key(&self, slot: &str) -> Option<String> {
+        match self.configuration.replace_policy {
+            ReplacePolicy::PerPlayer => Some(format!("{NOTIFICATION_SOURCE}:{slot}")),
+            ReplacePolicy::Global => Some(NOTIFICATION_SOURCE.to_string()),
+            ReplacePolicy::Stack => None,
+        }
+    }
+
+    // Actions are given as a flat list of alternating keys and labels.
+    fn actions(&self, sender: &str) -> Vec<String> {
+        self.configuration
+            .actions
+            .iter()
+            .flat_map(|action| {
+                [
+                    format!("{ACTION_KEY_PREFIX}:{}:{sender}", action.key()),
+                    action.label().to_string(),
+                ]
+            })
+            .collect()
+    }
+
     // Very permissive parsing algorithm (markup).
-    fn format_metadata(&self, fmt: &str, metadata: &PlayerMetadata) -> String {
-        FormattedNotification::new(fmt, metadata, &self.configuration.join_string).to_string()
+    fn format_metadata(
+        &self,
+        fmt: &str,
+        notification: &Notification,
+        position: Option<i64>,
+        markup: bool,
+    ) -> String {
+        FormattedNotification::new(fmt, &notification.metadata, &self.configuration.join_string)
+            .with_properties(&notification.properties)
+            .with_position(position)
+            .with_player(notification.active_player.as_deref())
+            .with_markup(markup)
+            .to_string()
+    }
+}
+
+/// A notification action button clicked by the user, addressed to the player
+/// which produced the notification.
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvokedAction {
+    pub sender: String,
+    pub action: PlayerAction,
+
+    // ID of the notification the action was invoked on.
+    id: u32,
+}
+
+impl InvokedAction {
+    // Parses an `ActionInvoked` signal. Signals for actions we didn't attach
+    // (such as the "default" action, or other applications' actions) yield
+    // `None`.
+    fn from_signal(signal: &MarshalledMessage) -> Result<Option<Self>, DBusError> {
+        if signal.dynheader.interface.as_deref() != Some(NOTIFICATION_NAMESPACE)
+            || signal.dynheader.member.as_deref() != Some(NOTIFICATION_ACTION_MEMBER)
+        {
+            return Ok(None);
+        }
+
+        let (id, key): (u32, &str) = signal.body.parser().get2()?;
+        Ok(Self::from_key(id, key))
+    }
+
+    fn from_key(id: u32, key: &str) -> Option<Self> {
+        let mut parts = key.splitn(3, ':');
+        if parts.next()? != ACTION_KEY_PREFIX {
+            return None;
+        }
+        let action = PlayerAction::from_str(parts.next()?).ok()?;
+        let sender = parts.next()?.to_string();
+        Some(Self { sender, action, id })
+    }
+}
+
+fn property_slot(sender: &str) -> String {
+    format!("{sender}{PROPERTY_SLOT_SUFFIX}")
+}
+
+// Convenience method to subscribe a DBusConnection to notification action
+// events (i.e., the user clicking a button on one of our notifications), and
+// to the notification server restarting.
+pub fn subscribe_notifications(dbus: &mut DBusConnection) -> Result<(), DBusError> {
+    dbus.subscribe(
+        NOTIFICATION_NAMESPACE,
+        NOTIFICATION_ACTION_MEMBER,
+        NOTIFICATION_OBJECTPATH,
+    )?;
+    subscribe_name_owner_changes(dbus, NameMatch::Name(NOTIFICATION_NAMESPACE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{property_slot, InvokedAction, Notifier, ReplacePolicy, ServerInformation};
+    use crate::configuration::Configuration;
+    use crate::mpris::PlayerAction;
+
+    #[test]
+    fn test_synchronous_key() {
+        let notifier = |replace_policy| {
+            Notifier::new(&Configuration {
+                replace_policy,
+                ..Configuration::default()
+            })
+        };
+
+        let per_player = notifier(ReplacePolicy::PerPlayer);
+        assert_ne!(
+            per_player.synchronous_key(":1.42"),
+            per_player.synchronous_key(":1.43")
+        );
+        let global = notifier(ReplacePolicy::Global);
+        assert_eq!(
+            global.synchronous_key(":1.42"),
+            global.synchronous_key(":1.43")
+        );
+        assert_eq!(
+            None,
+            notifier(ReplacePolicy::Stack).synchronous_key(":1.42")
+        );
+
+        // Property changes don't replace the player's track notification
+        assert_ne!(
+            per_player.synchronous_key(":1.42"),
+            per_player.synchronous_key(&property_slot(":1.42"))
+        );
+    }
+
+    #[test]
+    fn test_server_information_image_hints() {
+        let mut server = ServerInformation {
+            name: "mako".to_string(),
+            vendor: "emersion".to_string(),
+            version: "1.7".to_string(),
+            spec_version: "1.2".to_string(),
+            capabilities: vec!["body".to_string(), "body-markup".to_string()],
+        };
+        assert!(server.has_capability("body-markup"));
+        assert!(!server.has_capability("actions"));
+        assert_eq!("image-data", server.image_data_hint());
+        assert_eq!(Some("image-path"), server.image_path_hint());
+
+        server.spec_version = "1.1".to_string();
+        assert_eq!("image_data", server.image_data_hint());
+        assert_eq!(Some("image_path"), server.image_path_hint());
+
+        server.spec_version = "1.0".to_string();
+        assert_eq!("icon_data", server.image_data_hint());
+        assert_eq!(None, server.image_path_hint());
+
+        server.spec_version = "bogus".to_string();
+        assert_eq!("image-data", server.image_data_hint());
+        assert_eq!(Some("image-path"), server.image_path_hint());
+    }
+
+    #[test]
+    fn test_invoked_action_from_key() {
+        let expected = InvokedAction {
+            sender: ":1.42".to_string(),
+            action: PlayerAction::PlayPause,
+            id: 7,
+        };
+        assert_eq!(
+            Some(expected),
+            InvokedAction::from_key(7, "mpris-notifier:play_pause::1.42")
+        );
+        assert_eq!(None, InvokedAction::from_key(7, "default"));
+        assert_eq!(
+            None,
+            InvokedAction::from_key(7, "mpris-notifier:rewind::1.42")
+        );
+        assert_eq!(None, InvokedAction::from_key(7, "mpris-notifier:next"));
+    }
+
+    #[test]
+    fn test_accepts_action() {
+        let mut notifier = Notifier::new(&Configuration::default());
+        notifier.remember_notification(":1.42".to_string(), 7);
+        assert!(!notifier.accepts_action(7, Some(":1.5")));
+
+        notifier.server_owner = Some(":1.5".to_string());
+        assert!(notifier.accepts_action(7, Some(":1.5")));
+        assert!(!notifier.accepts_action(8, Some(":1.5")));
+        assert!(!notifier.accepts_action(7, Some(":1.66")));
+        assert!(!notifier.accepts_action(7, None));
+    }
+
+    #[test]
+    fn test_take_notification() {
+        let mut notifier = Notifier::new(&Configuration::default());
+        notifier.remember_notification(":1.42".to_string(), 7);
+        notifier.remember_notification(":1.43".to_string(), 8);
+        assert_eq!(7, notifier.replaces_id(":1.42"));
+
+        assert_eq!(Some(7), notifier.take_notification(":1.42"));
+        assert_eq!(None, notifier.take_notification(":1.42"));
+        assert_eq!(0, notifier.replaces_id(":1.42"));
+        assert_eq!(Some(8), notifier.last_id);
+
+        assert_eq!(Some(8), notifier.take_notification(":1.43"));
+        assert_eq!(None, notifier.last_id);
+
+        // Closing the track notification leaves the property change
+        // notification in its own slot
+        notifier.remember_notification(":1.44".to_string(), 9);
+        notifier.remember_notification(property_slot(":1.44"), 10);
+        assert_eq!(Some(9), notifier.take_notification(":1.44"));
+        assert_eq!(10, notifier.replaces_id(&property_slot(":1.44")));
+
+        // Forgotten notifications are neither replaced nor closed, in
+        // either slot
+        notifier.remember_notification(":1.44".to_string(), 11);
+        notifier.forget_notification(":1.44");
+        assert_eq!(0, notifier.replaces_id(":1.44"));
+        assert_eq!(0, notifier.replaces_id(&property_slot(":1.44")));
+        assert_eq!(None, notifier.take_notification(":1.44"));
     }
 }
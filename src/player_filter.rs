@@ -0,0 +1,184 @@
+use crate::mpris::{PlayerInfo, MPRIS_BUS_PREFIX};
+use crate::pattern::Pattern;
+use lazy_static::lazy_static;
+use regex::{Regex, RegexBuilder};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::fmt;
+
+lazy_static! {
+    static ref REGEX_PATTERN: Regex = Regex::new(r"^/(.*)/$").unwrap();
+}
+
+/// A pattern matched against a player's names, given either as a glob
+/// (`firefox*`, matched case-insensitively) or, when wrapped in slashes, as
+/// a regular expression (`/^chrom(e|ium)/`).
+///
+/// Patterns are compiled as the configuration is loaded, so that invalid
+/// patterns are reported up front.
+#[derive(Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct PlayerPattern(Pattern);
+
+impl PlayerPattern {
+    pub fn new(source: &str) -> Result<Self, regex::Error> {
+        let regex = match REGEX_PATTERN.captures(source) {
+            Some(caps) => Regex::new(&caps[1])?,
+            None => RegexBuilder::new(&glob_to_regex(source))
+                .case_insensitive(true)
+                .build()?,
+        };
+        Ok(Self(Pattern::with_regex(source, regex)))
+    }
+
+    /// Whether the pattern matches the player's well-known bus name (either
+    /// in full, or without the `org.mpris.MediaPlayer2.` prefix), or its
+    /// identity.
+    pub fn matches(&self, player: &PlayerInfo) -> bool {
+        let bus_name = player.bus_name.as_deref();
+        let short_name = bus_name.and_then(|name| name.strip_prefix(MPRIS_BUS_PREFIX));
+        [bus_name, short_name, player.identity.as_deref()]
+            .into_iter()
+            .flatten()
+            .any(|name| self.0.regex().is_match(name))
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            _ => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+impl fmt::Debug for PlayerPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<'de> Deserialize<'de> for PlayerPattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        PlayerPattern::new(&source)
+            .map_err(|err| de::Error::custom(format!("invalid pattern `{source}`: {err}")))
+    }
+}
+
+/// Whether notifications and commands should be generated for a player,
+/// given the include and exclude patterns. When include patterns are given,
+/// a player must match at least one of them; a player matching any exclude
+/// pattern is always rejected.
+pub fn is_player_allowed(
+    player: &PlayerInfo,
+    include: &[PlayerPattern],
+    exclude: &[PlayerPattern],
+) -> bool {
+    let included = include.is_empty() || include.iter().any(|p| p.matches(player));
+    included && !exclude.iter().any(|p| p.matches(player))
+}
+
+/// Rank of a player in a priority list: the index of the first pattern
+/// matching the player, where lower ranks take precedence. Players matching
+/// no pattern rank last.
+pub fn player_priority(player: &PlayerInfo, priority: &[PlayerPattern]) -> usize {
+    priority
+        .iter()
+        .position(|p| p.matches(player))
+        .unwrap_or(priority.len())
+}
+
+/// Determines which track notifications are sent when several players have
+/// notifications pending at once. Property change notifications and commands
+/// aren't subject to arbitration.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArbitrationPolicy {
+    /// Every player's notification is sent.
+    All,
+    /// Only the most recently changed player's notification is sent.
+    MostRecent,
+    /// Only the notification of the player ranked highest in the priority
+    /// list is sent; players of equal rank fall back to the most recent.
+    Priority,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_player_allowed, player_priority, PlayerPattern};
+    use crate::mpris::PlayerInfo;
+
+    #[test]
+    fn test_player_pattern_matches() {
+        let firefox = PlayerInfo::default()
+            .with_bus_name("org.mpris.MediaPlayer2.firefox.instance_1_23")
+            .with_identity("Mozilla Firefox");
+        let spotify = PlayerInfo::default()
+            .with_bus_name("org.mpris.MediaPlayer2.spotify")
+            .with_identity("Spotify");
+
+        let glob = PlayerPattern::new("firefox.*").unwrap();
+        assert!(glob.matches(&firefox));
+        assert!(!glob.matches(&spotify));
+
+        let identity = PlayerPattern::new("mozilla*").unwrap();
+        assert!(identity.matches(&firefox));
+
+        let full = PlayerPattern::new("org.mpris.MediaPlayer2.spotify").unwrap();
+        assert!(full.matches(&spotify));
+        assert!(!full.matches(&firefox));
+
+        let regex = PlayerPattern::new("/^(spot|chrom)/").unwrap();
+        assert!(regex.matches(&spotify));
+        assert!(!regex.matches(&firefox));
+
+        assert!(PlayerPattern::new("/(/").is_err());
+    }
+
+    #[test]
+    fn test_is_player_allowed() {
+        let firefox = PlayerInfo::default()
+            .with_bus_name("org.mpris.MediaPlayer2.firefox.instance_1_23")
+            .with_identity("Mozilla Firefox");
+        let spotify = PlayerInfo::default()
+            .with_bus_name("org.mpris.MediaPlayer2.spotify")
+            .with_identity("Spotify");
+        let anonymous = PlayerInfo::default();
+        let include = vec![PlayerPattern::new("spotify").unwrap()];
+        let exclude = vec![PlayerPattern::new("firefox*").unwrap()];
+
+        assert!(is_player_allowed(&firefox, &[], &[]));
+        assert!(is_player_allowed(&anonymous, &[], &exclude));
+        assert!(!is_player_allowed(&firefox, &[], &exclude));
+        assert!(is_player_allowed(&spotify, &[], &exclude));
+        assert!(is_player_allowed(&spotify, &include, &exclude));
+        assert!(!is_player_allowed(&anonymous, &include, &exclude));
+    }
+
+    #[test]
+    fn test_player_priority() {
+        let firefox = PlayerInfo::default()
+            .with_bus_name("org.mpris.MediaPlayer2.firefox.instance_1_23")
+            .with_identity("Mozilla Firefox");
+        let spotify = PlayerInfo::default()
+            .with_bus_name("org.mpris.MediaPlayer2.spotify")
+            .with_identity("Spotify");
+        let mpv = PlayerInfo::default()
+            .with_bus_name("org.mpris.MediaPlayer2.mpv")
+            .with_identity("mpv");
+        let priority = vec![
+            PlayerPattern::new("spotify").unwrap(),
+            PlayerPattern::new("firefox*").unwrap(),
+        ];
+
+        assert_eq!(0, player_priority(&spotify, &priority));
+        assert_eq!(1, player_priority(&firefox, &priority));
+        assert_eq!(2, player_priority(&mpv, &priority));
+        assert_eq!(0, player_priority(&mpv, &[]));
+    }
+}
@@ -0,0 +1,115 @@
+use crate::dbus::{DBusConnection, DBusError, NameOwnerChange};
+use crate::mpris::{is_player_bus_name, list_player_names};
+use std::collections::HashMap;
+
+/// Tracks which well-known `org.mpris.MediaPlayer2.*` bus name is owned by
+/// each player connection. Signals are only ever addressed from a player's
+/// unique name (e.g. `:1.42`), whereas users know players by their
+/// well-known name (e.g. `org.mpris.MediaPlayer2.spotify`).
+pub struct PlayerNames {
+    // Map from <D-Bus Sender> -> <Well-Known Bus Name>
+    bus_names: HashMap<String, String>,
+}
+
+impl PlayerNames {
+    pub fn new() -> Self {
+        Self {
+            bus_names: HashMap::new(),
+        }
+    }
+
+    /// Applies a bus name ownership change. Returns the unique name of a
+    /// player which has exited by releasing its MPRIS name, which also
+    /// happens when it disconnects from the bus.
+    pub fn handle_name_owner_change(&mut self, change: &NameOwnerChange) -> Option<String> {
+        if !is_player_bus_name(&change.name) {
+            return None;
+        }
+
+        if !change.new_owner.is_empty() {
+            log::debug!("Player {} appeared as {}", change.name, change.new_owner);
+            self.bus_names
+                .insert(change.new_owner.clone(), change.name.clone());
+        }
+        if !change.old_owner.is_empty() {
+            log::debug!("Player {} ({}) exited", change.name, change.old_owner);
+            self.bus_names.remove(&change.old_owner);
+            return Some(change.old_owner.clone());
+        }
+        None
+    }
+
+    /// Returns the well-known name owned by the given sender, asking the bus
+    /// if the sender hasn't been seen acquiring one (e.g., because it was
+    /// already running at startup).
+    pub fn bus_name(
+        &mut self,
+        sender: &str,
+        dbus: &mut DBusConnection,
+    ) -> Result<Option<String>, DBusError> {
+        if !self.bus_names.contains_key(sender) {
+            self.refresh(dbus)?;
+        }
+        Ok(self.bus_names.get(sender).cloned())
+    }
+
+    /// The unique names of all known players.
+    pub fn senders(&self) -> Vec<String> {
+        self.bus_names.keys().cloned().collect()
+    }
+
+    /// Rebuilds the map from the players currently on the bus.
+    pub fn refresh(&mut self, dbus: &mut DBusConnection) -> Result<(), DBusError> {
+        self.bus_names.clear();
+        for name in list_player_names(dbus)? {
+            match dbus.get_name_owner(&name) {
+                Ok(owner) => {
+                    self.bus_names.insert(owner, name);
+                }
+                // The player may have exited in the meantime
+                Err(err) => log::debug!("Unable to resolve owner of {}: {:?}", name, err),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PlayerNames;
+    use crate::dbus::NameOwnerChange;
+
+    fn change(name: &str, old_owner: &str, new_owner: &str) -> NameOwnerChange {
+        NameOwnerChange {
+            name: name.to_string(),
+            old_owner: old_owner.to_string(),
+            new_owner: new_owner.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_player_names_lifecycle() {
+        let mut names = PlayerNames::new();
+        let spotify = "org.mpris.MediaPlayer2.spotify";
+
+        assert_eq!(
+            None,
+            names.handle_name_owner_change(&change(spotify, "", ":1.42"))
+        );
+        assert_eq!(Some(&spotify.to_string()), names.bus_names.get(":1.42"));
+
+        // Unrelated names are ignored
+        assert_eq!(
+            None,
+            names.handle_name_owner_change(&change("org.example.Foo", "", ":1.43"))
+        );
+        assert_eq!(None, names.bus_names.get(":1.43"));
+
+        // Releasing the MPRIS name counts as exiting
+        assert_eq!(
+            Some(":1.42".to_string()),
+            names.handle_name_owner_change(&change(spotify, ":1.42", ""))
+        );
+        assert!(names.bus_names.is_empty());
+    }
+}
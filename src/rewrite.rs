@@ -0,0 +1,204 @@
+use crate::mpris::PlayerMetadata;
+use crate::pattern::Pattern;
+use serde::{Deserialize, Serialize};
+
+/// A metadata field which rewrite rules can match, or move text to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataField {
+    #[serde(alias = "track")]
+    Title,
+    Album,
+    #[serde(alias = "artists")]
+    Artist,
+    #[serde(alias = "album_artists")]
+    AlbumArtist,
+    #[serde(alias = "genres")]
+    Genre,
+}
+
+/// Rewrites a metadata field before it is used for anything else. Text
+/// matching the pattern is replaced with `replace` (which may refer to
+/// capture groups, e.g. `$1`), or stripped if no replacement is given. When
+/// `move_to` is set, the matched text (or its first capture group, if the
+/// pattern has one) is also added to that field: appended to the list of
+/// a list field, or to the end of a text field.
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize, Serialize)]
+pub struct RewriteRule {
+    pub field: MetadataField,
+    pub pattern: Pattern,
+    #[serde(default)]
+    pub replace: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub move_to: Option<MetadataField>,
+}
+
+impl RewriteRule {
+    // Applies the rule to a single value, returning the rewritten value and
+    // the text to move, if any.
+    fn rewrite(&self, value: &str) -> (String, Vec<String>) {
+        let regex = self.pattern.regex();
+        let moved = match self.move_to {
+            Some(_) => regex
+                .captures_iter(value)
+                .filter_map(|caps| caps.get(1).or_else(|| caps.get(0)))
+                .map(|m| m.as_str().trim().to_string())
+                .filter(|text| !text.is_empty())
+                .collect(),
+            None => Vec::new(),
+        };
+        let rewritten = regex.replace_all(value, self.replace.as_str());
+        (rewritten.trim().to_string(), moved)
+    }
+
+    fn apply(&self, metadata: &mut PlayerMetadata) {
+        let mut moved = Vec::new();
+        match field_mut(metadata, self.field) {
+            Field::Text(text) => {
+                if let Some(value) = text.as_deref() {
+                    let (rewritten, mut found) = self.rewrite(value);
+                    *text = Some(rewritten).filter(|value| !value.is_empty());
+                    moved.append(&mut found);
+                }
+            }
+            Field::List(list) => {
+                if let Some(values) = list.as_ref() {
+                    let mut rewritten = Vec::with_capacity(values.len());
+                    for value in values {
+                        let (value, mut found) = self.rewrite(value);
+                        if !value.is_empty() {
+                            rewritten.push(value);
+                        }
+                        moved.append(&mut found);
+                    }
+                    *list = Some(rewritten);
+                }
+            }
+        }
+
+        if let (Some(target), false) = (self.move_to, moved.is_empty()) {
+            match field_mut(metadata, target) {
+                // Text fields keep their value, with the moved text
+                // appended after a space.
+                Field::Text(text) => {
+                    let moved = moved.join(" ");
+                    *text = Some(match text.take() {
+                        Some(existing) => format!("{existing} {moved}"),
+                        None => moved,
+                    });
+                }
+                Field::List(list) => list.get_or_insert_with(Vec::new).extend(moved),
+            }
+        }
+    }
+}
+
+enum Field<'a> {
+    Text(&'a mut Option<String>),
+    List(&'a mut Option<Vec<String>>),
+}
+
+fn field_mut(metadata: &mut PlayerMetadata, field: MetadataField) -> Field<'_> {
+    match field {
+        MetadataField::Title => Field::Text(&mut metadata.title),
+        MetadataField::Album => Field::Text(&mut metadata.album),
+        MetadataField::Artist => Field::List(&mut metadata.artists),
+        MetadataField::AlbumArtist => Field::List(&mut metadata.album_artists),
+        MetadataField::Genre => Field::List(&mut metadata.genres),
+    }
+}
+
+/// Applies the rules to the metadata, in order.
+pub fn rewrite_metadata(rules: &[RewriteRule], metadata: &mut PlayerMetadata) {
+    for rule in rules {
+        rule.apply(metadata);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rewrite_metadata, MetadataField, RewriteRule};
+    use crate::mpris::PlayerMetadata;
+    use crate::pattern::Pattern;
+
+    fn rule(field: MetadataField, pattern: &str, move_to: Option<MetadataField>) -> RewriteRule {
+        RewriteRule {
+            field,
+            pattern: Pattern::new(pattern).unwrap(),
+            replace: String::new(),
+            move_to,
+        }
+    }
+
+    #[test]
+    fn test_rewrite_metadata() {
+        let rules = vec![
+            rule(MetadataField::Title, r" - Remastered \d{4}$", None),
+            rule(MetadataField::Title, r"(?i)\(official video\)", None),
+            rule(MetadataField::Artist, r" - Topic$", None),
+            rule(
+                MetadataField::Title,
+                r"\s*\(feat\. ([^)]+)\)",
+                Some(MetadataField::Artist),
+            ),
+            RewriteRule {
+                replace: "$1 Mix".to_string(),
+                ..rule(MetadataField::Album, r"(\w+) Remix", None)
+            },
+        ];
+        let mut metadata = PlayerMetadata {
+            title: Some(
+                "Ghost Town (feat. Kid Cudi) (Official Video) - Remastered 2011".to_string(),
+            ),
+            artists: Some(vec!["Kanye West - Topic".to_string()]),
+            album: Some("Club Remix".to_string()),
+            genres: Some(vec!["Hip-Hop".to_string()]),
+            ..PlayerMetadata::default()
+        };
+        rewrite_metadata(&rules, &mut metadata);
+
+        assert_eq!(Some("Ghost Town".to_string()), metadata.title);
+        assert_eq!(
+            Some(vec!["Kanye West".to_string(), "Kid Cudi".to_string()]),
+            metadata.artists
+        );
+        assert_eq!(Some("Club Mix".to_string()), metadata.album);
+        assert_eq!(Some(vec!["Hip-Hop".to_string()]), metadata.genres);
+
+        // Fields stripped entirely are unset
+        let mut metadata = PlayerMetadata {
+            title: Some("(Official Video)".to_string()),
+            ..PlayerMetadata::default()
+        };
+        rewrite_metadata(&rules, &mut metadata);
+        assert_eq!(None, metadata.title);
+    }
+
+    #[test]
+    fn test_rewrite_metadata_move_to_text() {
+        let rules = vec![rule(
+            MetadataField::Title,
+            r"\s*\[(Live[^\]]*)\]",
+            Some(MetadataField::Album),
+        )];
+        let mut metadata = PlayerMetadata {
+            title: Some("Heroes [Live at Wembley]".to_string()),
+            album: Some("A Reality Tour".to_string()),
+            ..PlayerMetadata::default()
+        };
+        rewrite_metadata(&rules, &mut metadata);
+        assert_eq!(Some("Heroes".to_string()), metadata.title);
+        assert_eq!(
+            Some("A Reality Tour Live at Wembley".to_string()),
+            metadata.album
+        );
+
+        // Text is moved to an unset field as-is
+        let mut metadata = PlayerMetadata {
+            title: Some("Heroes [Live]".to_string()),
+            ..PlayerMetadata::default()
+        };
+        rewrite_metadata(&rules, &mut metadata);
+        assert_eq!(Some("Live".to_string()), metadata.album);
+    }
+}
@@ -1,14 +1,30 @@
 #[cfg(feature = "album-art")]
 use crate::art::ArtFetcher;
 
+use crate::active_player::ActivePlayers;
+use crate::ads::AdDetector;
+use crate::dbus::NameOwnerChange;
+use crate::dedupe::{fingerprint, RecentTracks};
+use crate::formatter::percentage;
+use crate::media_type::classify;
+use crate::mpris::call_player_action;
+use crate::mpris::fetch_player_info;
+use crate::mpris::fetch_player_properties;
+use crate::mpris::is_player_bus_name;
 use crate::mpris::MprisPropertiesChange;
+use crate::mpris::PlayerInfo;
 use crate::mpris::PlayerMetadata;
+use crate::mpris::PlayerProperties;
 use crate::mpris::PlayerStatus;
 use crate::notifier::Notification;
+use crate::player_filter::{is_player_allowed, player_priority, ArbitrationPolicy};
+use crate::players::PlayerNames;
+use crate::rewrite::rewrite_metadata;
+use crate::stream::{is_stream, set_station, split_stream_title};
 use crate::DBusError;
 use crate::{configuration::Configuration, dbus::DBusConnection, notifier::Notifier};
 use rustbus::message_builder::MarshalledMessage;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::process::Command;
 use std::time::Duration;
 use std::time::Instant;
@@ -20,6 +36,21 @@ use thiserror::Error;
 // metadata fields.
 const NOTIFICATION_DELAY: Duration = Duration::from_millis(250);
 
+// Environment variables describing the active player, passed to commands.
+const PLAYER_ENV: &str = "MPRIS_NOTIFIER_PLAYER";
+const PLAYER_BUS_NAME_ENV: &str = "MPRIS_NOTIFIER_PLAYER_BUS_NAME";
+
+// What a property change means for a player's playback.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum PlaybackEvent {
+    // Playback moved on to a track other than the last notified one.
+    NewTrack,
+    // Playback resumed on the last notified track.
+    Resumed,
+    Paused,
+    Stopped,
+}
+
 #[derive(Debug, Error)]
 pub enum SignalHandlerError {
     #[error("error handling D-Bus signal")]
@@ -30,15 +61,45 @@ pub struct SignalHandler {
     configuration: Configuration,
     notifier: Notifier,
     art_fetcher: ArtFetcher,
+    ad_detector: AdDetector,
 
     // Map from <D-Bus Sender> -> <Last Received Metadata>
     metadata: HashMap<String, PlayerMetadata>,
 
-    // Notification that will be sent after [NOTIFICATION_DELAY] passes.
-    pending_notification: Option<Notification>,
+    // Map from <D-Bus Sender> -> <Last Known Playback Properties>
+    properties: HashMap<String, PlayerProperties>,
+
+    // Map from <D-Bus Sender> -> <Metadata Of The Last Notified Track>
+    notified_tracks: HashMap<String, PlayerMetadata>,
+
+    // Tracks recently notified by any player, to suppress duplicates.
+    recent_tracks: RecentTracks,
+
+    // Senders currently playing ads, so that each ad break is only notified
+    // once.
+    ad_breaks: HashSet<String>,
+
+    // Map from <D-Bus Sender> -> <Player Identity>
+    player_info: HashMap<String, PlayerInfo>,
+
+    // Well-known bus names of the running players.
+    player_names: PlayerNames,
+
+    // Players ranked by which one is currently active.
+    active_players: ActivePlayers,
+
+    // Map from <D-Bus Sender> -> <Notification>, where each notification
+    // is sent once [NOTIFICATION_DELAY] passes since its last update.
+    pending_notifications: HashMap<String, Notification>,
+
+    // Property change notification (e.g. volume), sent after the same delay
+    // so that a burst of changes only produces a single notification.
+    pending_property_notification: Option<Notification>,
 
-    // Commands that will be called on MPRIS DBUS signals.
+    // Commands that will be called on MPRIS DBUS signals, once
+    // [NOTIFICATION_DELAY] passes since the last signal queued them.
     pending_commands: Vec<Command>,
+    commands_touched: Instant,
 }
 
 impl SignalHandler {
@@ -47,31 +108,139 @@ impl SignalHandler {
             configuration: configuration.clone(),
             notifier: Notifier::new(configuration),
             art_fetcher: ArtFetcher::new(configuration),
+            ad_detector: AdDetector::new(configuration),
             metadata: HashMap::new(),
-            pending_notification: None,
+            properties: HashMap::new(),
+            notified_tracks: HashMap::new(),
+            recent_tracks: RecentTracks::new(Duration::from_millis(
+                configuration.duplicate_window.into(),
+            )),
+            ad_breaks: HashSet::new(),
+            player_info: HashMap::new(),
+            player_names: PlayerNames::new(),
+            active_players: ActivePlayers::new(&configuration.player_priority),
+            pending_notifications: HashMap::new(),
+            pending_property_notification: None,
             pending_commands: Vec::new(),
+            commands_touched: Instant::now(),
+        }
+    }
+
+    // Called once from the main loop after connecting to D-Bus.
+    pub fn initialize(&mut self, dbus: &mut DBusConnection) {
+        if let Err(err) = self.notifier.refresh_server_information(dbus) {
+            log::warn!("Unable to query notification server: {:?}", err);
+        }
+        if let Err(err) = self.sync_players(dbus) {
+            log::warn!("Unable to query running players: {:?}", err);
+        }
+    }
+
+    // Seeds the metadata cache from players which were already running
+    // before startup, so that their next status change can be notified.
+    fn sync_players(&mut self, dbus: &mut DBusConnection) -> Result<(), SignalHandlerError> {
+        self.player_names.refresh(dbus)?;
+
+        for sender in self.player_names.senders() {
+            let player = self.player_info(&sender, dbus);
+            if !is_player_allowed(
+                &player,
+                &self.configuration.player_include,
+                &self.configuration.player_exclude,
+            ) {
+                continue;
+            }
+
+            let state = match fetch_player_properties(dbus, &sender) {
+                Ok(state) => state,
+                Err(err) => {
+                    log::warn!("Unable to query state of {}: {:?}", sender, err);
+                    continue;
+                }
+            };
+            self.properties.insert(sender.clone(), state.properties);
+            if let Some(status) = &state.status {
+                self.active_players
+                    .handle_status(&sender, &player, status.clone());
+            }
+            let mut metadata = match state.metadata {
+                Some(metadata) => metadata,
+                None => continue,
+            };
+            self.prepare_metadata(&sender, &mut metadata);
+            log::debug!("Found running player {:?}: {:?}", player, metadata);
+
+            if self.ad_detector.is_ad(&metadata) {
+                self.ad_breaks.insert(sender.clone());
+            } else if self.configuration.notify_on_startup
+                && state.status == Some(PlayerStatus::Playing)
+                && !self.is_duplicate(&sender, &metadata)
+            {
+                if let Some(notification) = self.track_notification(&sender, &player, &metadata) {
+                    self.queue_notification(notification);
+                    self.attach_album_art(&sender);
+                }
+            }
+            self.metadata.insert(sender, metadata);
         }
+
+        Ok(())
     }
 
     // Must be called regularly from the main loop. Used to fire notifications
     // on a timer.
     pub fn handle_pending(&mut self, dbus: &mut DBusConnection) -> Result<(), SignalHandlerError> {
-        if let Some(pending) = &self.pending_notification {
+        let now = Instant::now();
+        let commands = self.take_due_commands(now);
+        let due: Vec<String> = self
+            .pending_notifications
+            .iter()
+            .filter(|(_, pending)| now - pending.last_touched() > NOTIFICATION_DELAY)
+            .map(|(sender, _)| sender.clone())
+            .collect();
+
+        let active = self.active_players.active();
+        let active_name = active.and_then(|player| player.info.name());
+
+        for sender in due {
+            let notification = self.pending_notifications.remove(&sender).unwrap();
+            let notification = if self.ad_detector.is_ad(notification.metadata()) {
+                // An ad break names the player playing the ads, which may not
+                // be the active one.
+                let name = notification.player().name().map(str::to_string);
+                notification.with_active_player(name.as_deref())
+            } else {
+                if let Some(fingerprint) = fingerprint(notification.metadata()) {
+                    self.recent_tracks.record(fingerprint, now);
+                }
+                self.notified_tracks
+                    .insert(sender, notification.metadata().clone());
+                notification.with_active_player(active_name)
+            };
+            self.notifier.send_notification(notification, dbus)?;
+        }
+
+        for mut command in commands {
+            if let Some(name) = active_name {
+                command.env(PLAYER_ENV, name);
+            }
+            if let Some(bus_name) = active.and_then(|player| player.info.bus_name.as_deref()) {
+                command.env(PLAYER_BUS_NAME_ENV, bus_name);
+            }
+            match command.output() {
+                Ok(_) => (),
+                Err(err) => {
+                    log::warn!("Command failed: {}", err);
+                }
+            }
+        }
+
+        if let Some(pending) = &self.pending_property_notification {
             let delta = Instant::now() - pending.last_touched();
             if delta > NOTIFICATION_DELAY {
+                let notification = self.pending_property_notification.take().unwrap();
                 self.notifier
-                    .send_notification(self.pending_notification.take().unwrap(), dbus)?;
-
-                for command in self.pending_commands.iter_mut() {
-                    match command.output() {
-                        Ok(_) => (),
-                        Err(err) => {
-                            log::warn!("Command failed: {}", err);
-                        }
-                    }
-                }
-
-                self.pending_commands.clear();
+                    .send_notification(notification.with_active_player(active_name), dbus)?;
             }
         }
 
@@ -81,7 +250,33 @@ impl SignalHandler {
     // Called from the main loop for every received signal. Sets the pending
     // notification, but does not emit the notification; use [handle_pending]
     // to send the notification.
-    pub fn handle_signal(&mut self, signal: MarshalledMessage) -> Result<(), SignalHandlerError> {
+    pub fn handle_signal(
+        &mut self,
+        signal: MarshalledMessage,
+        dbus: &mut DBusConnection,
+    ) -> Result<(), SignalHandlerError> {
+        // Notification buttons are forwarded to the player that sent the
+        // original track change.
+        if let Some(invoked) = self.notifier.invoked_action(&signal)? {
+            log::debug!("Invoking {:?} on {}", invoked.action, invoked.sender);
+            call_player_action(dbus, &invoked.sender, invoked.action)?;
+            return Ok(());
+        }
+
+        if let Some(change) = NameOwnerChange::from_signal(&signal)? {
+            if let Some(exited) = self.player_names.handle_name_owner_change(&change) {
+                self.evict_player(&exited, dbus)?;
+            } else if !change.new_owner.is_empty() && is_player_bus_name(&change.name) {
+                if let Some(info) = self.player_info.get_mut(&change.new_owner) {
+                    info.bus_name = Some(change.name.clone());
+                }
+                self.active_players
+                    .set_bus_name(&change.new_owner, &change.name);
+            }
+            self.notifier.handle_name_owner_change(&change, dbus)?;
+            return Ok(());
+        }
+
         let sender = signal
             .dynheader
             .sender
@@ -95,6 +290,32 @@ impl SignalHandler {
             return Ok(());
         }
 
+        // As are players which were filtered out by the configuration
+        let player = self.player_info(&sender, dbus);
+        if !is_player_allowed(
+            &player,
+            &self.configuration.player_include,
+            &self.configuration.player_exclude,
+        ) {
+            log::debug!("Ignoring signal from filtered player {:?}", player);
+            return Ok(());
+        }
+
+        if self.handle_change(sender.clone(), player, change.unwrap()) {
+            self.notifier.close_notification(&sender, dbus)?;
+        }
+        Ok(())
+    }
+
+    // Applies a property change of an allowed player, queueing the
+    // notifications it calls for. Returns whether the player's notification
+    // should be closed instead.
+    fn handle_change(
+        &mut self,
+        sender: String,
+        player: PlayerInfo,
+        mut change: MprisPropertiesChange,
+    ) -> bool {
         // Call commands for all signals, so that external programs are called
         // on pause and play.
         if let Some(commands) = self.configuration.commands.as_ref() {
@@ -103,86 +324,602 @@ impl SignalHandler {
                 .filter_map(|command_args| match command_args.len() {
                     0 => None,
                     1 => Some(Command::new(command_args[0].as_str())),
-                    2.. => {
+                    _ => {
                         let mut cmd = Command::new(command_args[0].as_str());
                         cmd.args(&command_args[1..command_args.len()]);
                         Some(cmd)
                     }
-                    _ => None,
                 })
                 .collect();
+            self.commands_touched = Instant::now();
         }
 
-        let change = change.unwrap();
+        // Metadata is rewritten before anything else sees it.
+        if let Some(metadata) = change.metadata.as_mut() {
+            self.prepare_metadata(&sender, metadata);
+        }
+        if let Some(status) = &change.status {
+            self.active_players
+                .handle_status(&sender, &player, status.clone());
+        }
+        self.handle_properties(&sender, &player, &change.properties);
 
-        // Handle metadata property changes.
-        //
         // Incoming metadata property changes are cached per each sender,
         // where the most recently received metadata is cached in its
         // entirety.
-        //
-        // A property change always queues up a notification to be sent.
-        let mut metadata: Option<&PlayerMetadata> = self.metadata.get(&sender);
-        if let Some(new_metadata) = change.metadata {
+        if let Some(new_metadata) = &change.metadata {
             self.metadata
                 .insert(sender.to_string(), new_metadata.clone());
-            metadata = self.metadata.get(&sender);
-
-            // If our current notification is from the same sender, update it.
-            // Otherwise, wipe out whatever was being built and start
-            // hydrating a new Notification.
-            let pending = self.pending_notification.as_mut();
-            if let Some(pending) = pending {
-                if pending.sender() == sender {
-                    pending.update(&new_metadata, None);
+        }
+
+        // If we haven't gotten metadata yet, we can't notify
+        let metadata = match self.metadata.get(&sender) {
+            Some(metadata) => metadata.clone(),
+            None => return false,
+        };
+
+        // Ads don't produce notifications or run commands, and aren't
+        // recorded as notified, so the track after an ad break is compared
+        // to the one before it.
+        if self.ad_detector.is_ad(&metadata) {
+            self.pending_commands.clear();
+            if self.ad_breaks.insert(sender.clone()) {
+                log::debug!("Ad break on {:?}", player);
+                self.pending_notifications.remove(&sender);
+                if self.configuration.notify_on_ad_break {
+                    let notification = Notification::new(&sender, &player, &metadata, None)
+                        .with_formats(
+                            &self.configuration.ad_break_subject_format,
+                            &self.configuration.ad_break_body_format,
+                        )
+                        .with_album_art_enabled(Some(false));
+                    self.queue_notification(notification);
                 }
-            } else {
-                self.pending_notification = Some(Notification::new(&sender, &new_metadata, None));
             }
+            return false;
         }
+        self.ad_breaks.remove(&sender);
 
-        // If we haven't gotten metadata yet, we can't notify
-        if metadata.is_none() {
-            return Ok(());
+        let configuration = &self.configuration;
+        match self.classify_event(&sender, &change, &metadata) {
+            Some(PlaybackEvent::NewTrack) => {
+                if configuration.notify_on_new_track {
+                    if self.is_duplicate(&sender, &metadata) {
+                        // Recorded as notified, so that later signals for the
+                        // same track aren't taken as a new track once the
+                        // window has passed.
+                        log::debug!("Skipping duplicate notification from {:?}", player);
+                        self.notified_tracks
+                            .insert(sender.clone(), metadata.clone());
+                    } else if let Some(notification) =
+                        self.track_notification(&sender, &player, &metadata)
+                    {
+                        self.queue_notification(notification);
+                    }
+                }
+            }
+            Some(PlaybackEvent::Resumed) => {
+                if configuration.notify_on_resume {
+                    if let Some(notification) = self.track_notification(&sender, &player, &metadata)
+                    {
+                        self.queue_notification(notification.with_formats(
+                            &configuration.resume_subject_format,
+                            &configuration.resume_body_format,
+                        ));
+                    }
+                }
+            }
+            Some(event @ (PlaybackEvent::Paused | PlaybackEvent::Stopped)) => {
+                self.pending_notifications.remove(&sender);
+
+                let (close, notify, subject_format, body_format) = match event {
+                    PlaybackEvent::Paused => (
+                        configuration.close_on_pause,
+                        configuration.notify_on_pause,
+                        &configuration.pause_subject_format,
+                        &configuration.pause_body_format,
+                    ),
+                    _ => (
+                        configuration.close_on_stop,
+                        configuration.notify_on_stop,
+                        &configuration.stop_subject_format,
+                        &configuration.stop_body_format,
+                    ),
+                };
+                if close {
+                    return true;
+                }
+                if notify {
+                    if let Some(notification) = self.track_notification(&sender, &player, &metadata)
+                    {
+                        self.queue_notification(
+                            notification.with_formats(subject_format, body_format),
+                        );
+                    }
+                }
+            }
+            None => {
+                // Metadata refreshes of the same track (e.g. players adding
+                // fields in later signals) update a notification which is
+                // still pending.
+                if let Some(pending) = self.pending_notifications.get_mut(&sender) {
+                    if change.metadata.is_some() {
+                        pending.update(&metadata, None);
+                    }
+                }
+            }
         }
-        let metadata = metadata.unwrap();
 
-        // Handle playback status.
-        //
-        // When the 'Playing' signal is sent, queue that sender's track
-        // for notification (either they're resuming play, or changing
-        // tracks).
-        if let Some(status) = change.status {
-            if status == PlayerStatus::Playing {
-                self.pending_notification = Some(Notification::new(&sender, metadata, None));
+        self.attach_album_art(&sender);
+        false
+    }
+
+    // Whether the track was notified within the duplicate window, or is about
+    // to be notified for another player.
+    fn is_duplicate(&self, sender: &str, metadata: &PlayerMetadata) -> bool {
+        if !self.recent_tracks.is_enabled() {
+            return false;
+        }
+        let track = match fingerprint(metadata) {
+            Some(track) => track,
+            None => return false,
+        };
+
+        self.recent_tracks.contains(&track, Instant::now())
+            || self
+                .pending_notifications
+                .iter()
+                .filter(|(other, _)| other.as_str() != sender)
+                .any(|(_, pending)| fingerprint(pending.metadata()).as_ref() == Some(&track))
+    }
+
+    // Builds a notification for the track using the settings for its type of
+    // media, or None if notifications for that type are disabled. The
+    // per-type formats replace the new track formats only.
+    fn track_notification(
+        &self,
+        sender: &str,
+        player: &PlayerInfo,
+        metadata: &PlayerMetadata,
+    ) -> Option<Notification> {
+        let media = self
+            .configuration
+            .media_settings(classify(metadata, player));
+        if !media.notify {
+            return None;
+        }
+
+        let notification = Notification::new(sender, player, metadata, None)
+            .with_album_art_enabled(media.enable_album_art);
+        if media.subject_format.is_none() && media.body_format.is_none() {
+            return Some(notification);
+        }
+        let subject_format = media
+            .subject_format
+            .as_deref()
+            .unwrap_or(&self.configuration.subject_format);
+        let body_format = media
+            .body_format
+            .as_deref()
+            .unwrap_or(&self.configuration.body_format);
+        Some(notification.with_formats(subject_format, body_format))
+    }
+
+    // Updates the cached playback properties of a player, queueing a
+    // notification for the enabled properties whose value changed. Values
+    // seen for the first time are only recorded, since there is nothing to
+    // compare them against.
+    fn handle_properties(&mut self, sender: &str, player: &PlayerInfo, change: &PlayerProperties) {
+        let previous = self.properties.entry(sender.to_string()).or_default();
+        let current = {
+            let mut current = previous.clone();
+            current.update(change);
+            current
+        };
+        fn changed<T: PartialEq>(previous: &Option<T>, current: &Option<T>) -> bool {
+            previous.is_some() && previous != current
+        }
+
+        let configuration = &self.configuration;
+        let mut notification: Option<(&str, Option<i32>)> = None;
+        if configuration.notify_on_shuffle && changed(&previous.shuffle, &current.shuffle) {
+            notification = Some((&configuration.shuffle_format, None));
+        }
+        if configuration.notify_on_loop_status
+            && changed(&previous.loop_status, &current.loop_status)
+        {
+            notification = Some((&configuration.loop_status_format, None));
+        }
+        if configuration.notify_on_rate && changed(&previous.rate, &current.rate) {
+            notification = Some((&configuration.rate_format, None));
+        }
+        // Volume is compared as a percentage, as some players report
+        // insignificant floating point jitter.
+        let volume = current.volume.map(percentage);
+        if configuration.notify_on_volume && changed(&previous.volume.map(percentage), &volume) {
+            notification = Some((&configuration.volume_format, volume));
+        }
+
+        if let Some((format, value)) = notification {
+            let metadata = self.metadata.get(sender).cloned().unwrap_or_default();
+            let mut pending = Notification::new(sender, player, &metadata, None)
+                .with_properties(&current)
+                .with_formats(format, "")
+                .for_property_change();
+            if let Some(value) = value {
+                pending = pending.with_value(value);
+            }
+            self.pending_property_notification = Some(pending);
+        }
+        *previous = current;
+    }
+
+    // Fills in stream details and applies the rewrite rules to newly received
+    // metadata. A stream's title changing makes for a different track, even
+    // though its track ID stays the same.
+    fn prepare_metadata(&self, sender: &str, metadata: &mut PlayerMetadata) {
+        if is_stream(metadata, self.metadata.get(sender)) {
+            set_station(metadata);
+            if self.configuration.split_stream_titles {
+                split_stream_title(metadata, self.configuration.stream_title_separator.regex());
+            }
+        }
+        rewrite_metadata(&self.configuration.rewrite_rules, metadata);
+    }
+
+    // Determines what a property change means for the player's playback,
+    // relative to the track it was last notified for.
+    fn classify_event(
+        &self,
+        sender: &str,
+        change: &MprisPropertiesChange,
+        metadata: &PlayerMetadata,
+    ) -> Option<PlaybackEvent> {
+        match change.status {
+            Some(PlayerStatus::Paused) => return Some(PlaybackEvent::Paused),
+            Some(PlayerStatus::Stopped) => return Some(PlaybackEvent::Stopped),
+            _ => (),
+        }
+
+        let is_new_track = !self
+            .notified_tracks
+            .get(sender)
+            .is_some_and(|notified| notified.is_same_track(metadata));
+        let is_pending = self
+            .pending_notifications
+            .get(sender)
+            .is_some_and(|pending| pending.metadata().is_same_track(metadata));
+
+        if change.status == Some(PlayerStatus::Playing) {
+            if is_new_track {
+                Some(PlaybackEvent::NewTrack)
             } else {
-                self.pending_notification = None;
+                Some(PlaybackEvent::Resumed)
             }
+        } else if change.metadata.is_some() && is_new_track && !is_pending {
+            Some(PlaybackEvent::NewTrack)
+        } else {
+            None
         }
+    }
 
-        //  We can't notify if the pending notification is still empty
-        if self.pending_notification.as_mut().is_none() {
-            return Ok(());
+    // Queues a track notification, replacing the sender's pending
+    // notification. Notifications pending for other players are kept or
+    // dropped according to the arbitration policy. Property change
+    // notifications and commands bypass this.
+    // Takes the pending commands once the delay since they were queued has
+    // passed, whether or not the signals also queued a notification.
+    fn take_due_commands(&mut self, now: Instant) -> Vec<Command> {
+        if now - self.commands_touched > NOTIFICATION_DELAY {
+            std::mem::take(&mut self.pending_commands)
+        } else {
+            Vec::new()
         }
-        let pending = self.pending_notification.as_mut().unwrap();
+    }
+
+    fn queue_notification(&mut self, notification: Notification) {
+        let sender = notification.sender().to_string();
+        match self.configuration.arbitration_policy {
+            ArbitrationPolicy::All => (),
+            ArbitrationPolicy::MostRecent => self.pending_notifications.clear(),
+            ArbitrationPolicy::Priority => {
+                let rank = self.priority(&sender);
+                let outranked = self
+                    .pending_notifications
+                    .keys()
+                    .any(|pending| *pending != sender && self.priority(pending) < rank);
+                if outranked {
+                    log::debug!("Dropping notification of lower priority player {}", sender);
+                    return;
+                }
+                self.pending_notifications.clear();
+            }
+        }
+        self.pending_notifications.insert(sender, notification);
+    }
+
+    // Rank of a player in the configured priority list.
+    fn priority(&self, sender: &str) -> usize {
+        let player = self.player_info.get(sender).cloned().unwrap_or_default();
+        player_priority(&player, &self.configuration.player_priority)
+    }
 
-        // Fetch album art to a temporary buffer in the pending notification,
-        // if the feature is enabled.
+    // Fetches album art to a temporary buffer in the sender's pending
+    // notification, if the feature is enabled.
+    fn attach_album_art(&mut self, sender: &str) {
+        //  We can't fetch art if the pending notification is still empty
+        let pending = match self.pending_notifications.get_mut(sender) {
+            Some(pending) => pending,
+            None => return,
+        };
+        let metadata = pending.metadata().clone();
+
+        #[cfg(feature = "album-art")]
+        let enabled = pending
+            .album_art_enabled()
+            .unwrap_or(self.configuration.enable_album_art);
         #[cfg(feature = "album-art")]
-        if metadata.art_url.is_some() && self.configuration.enable_album_art {
-            let result = self
-                .art_fetcher
-                .get_album_art(metadata.art_url.as_ref().unwrap());
+        if let (Some(art_url), true) = (&metadata.art_url, enabled) {
+            if !self.notifier.wants_image_data(art_url) {
+                return;
+            }
+            let result = self.art_fetcher.get_album_art(art_url);
             match result {
                 Ok(data) => {
-                    pending.update(metadata, Some(data));
+                    pending.update(&metadata, Some(data));
                 }
                 Err(err) => {
                     log::warn!("Error fetching album art for {:#?}: {}", &metadata, err);
                 }
             }
         }
+    }
+
+    // Returns the cached identity of the given sender, querying the player
+    // the first time it is seen. Players which fail to answer are treated as
+    // anonymous, and queried again on their next signal.
+    fn player_info(&mut self, sender: &str, dbus: &mut DBusConnection) -> PlayerInfo {
+        if let Some(info) = self.player_info.get(sender) {
+            return info.clone();
+        }
 
+        let bus_name = self
+            .player_names
+            .bus_name(sender, dbus)
+            .unwrap_or_else(|err| {
+                log::warn!("Unable to resolve bus name of {}: {:?}", sender, err);
+                None
+            });
+        match fetch_player_info(dbus, sender) {
+            Ok(info) => {
+                let info = PlayerInfo { bus_name, ..info };
+                self.player_info.insert(sender.to_string(), info.clone());
+                info
+            }
+            Err(err) => {
+                log::warn!("Unable to query player {}: {:?}", sender, err);
+                PlayerInfo {
+                    bus_name,
+                    ..PlayerInfo::default()
+                }
+            }
+        }
+    }
+
+    // Drops all state kept for a player which has exited.
+    fn evict_player(
+        &mut self,
+        sender: &str,
+        dbus: &mut DBusConnection,
+    ) -> Result<(), SignalHandlerError> {
+        self.metadata.remove(sender);
+        self.properties.remove(sender);
+        self.active_players.remove(sender);
+        self.notified_tracks.remove(sender);
+        self.ad_breaks.remove(sender);
+        self.player_info.remove(sender);
+        self.pending_notifications.remove(sender);
+        if self
+            .pending_property_notification
+            .as_ref()
+            .is_some_and(|pending| pending.sender() == sender)
+        {
+            self.pending_property_notification = None;
+        }
+
+        if self.configuration.close_on_exit {
+            self.notifier.close_notification(sender, dbus)?;
+        } else {
+            self.notifier.forget_notification(sender);
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{PlaybackEvent, SignalHandler, NOTIFICATION_DELAY};
+    use crate::configuration::Configuration;
+    use crate::mpris::{
+        MprisPropertiesChange, PlayerInfo, PlayerMetadata, PlayerProperties, PlayerStatus,
+    };
+    use std::time::Instant;
+
+    const SENDER: &str = ":1.42";
+
+    fn change(
+        status: Option<PlayerStatus>,
+        metadata: Option<PlayerMetadata>,
+    ) -> MprisPropertiesChange {
+        MprisPropertiesChange {
+            status,
+            metadata,
+            properties: PlayerProperties::default(),
+        }
+    }
+
+    #[test]
+    fn test_classify_event() {
+        let mut handler = SignalHandler::new(&Configuration::default());
+        let numbers = PlayerMetadata::default()
+            .with_track_id("/org/mpris/track/1")
+            .with_title("Numbers")
+            .with_artists(&["Kraftwerk"])
+            .with_album("Computer World");
+        let pocket_calculator = PlayerMetadata::default()
+            .with_track_id("/org/mpris/track/2")
+            .with_title("Pocket Calculator")
+            .with_artists(&["Kraftwerk"])
+            .with_album("Computer World");
+
+        let new_track = change(None, Some(numbers.clone()));
+        assert_eq!(
+            Some(PlaybackEvent::NewTrack),
+            handler.classify_event(SENDER, &new_track, &numbers)
+        );
+
+        handler
+            .notified_tracks
+            .insert(SENDER.to_string(), numbers.clone());
+        let playing = change(Some(PlayerStatus::Playing), None);
+        assert_eq!(
+            Some(PlaybackEvent::Resumed),
+            handler.classify_event(SENDER, &playing, &numbers)
+        );
+        assert_eq!(
+            Some(PlaybackEvent::NewTrack),
+            handler.classify_event(SENDER, &playing, &pocket_calculator)
+        );
+        assert_eq!(None, handler.classify_event(SENDER, &new_track, &numbers));
+
+        let paused = change(Some(PlayerStatus::Paused), None);
+        assert_eq!(
+            Some(PlaybackEvent::Paused),
+            handler.classify_event(SENDER, &paused, &numbers)
+        );
+        let stopped = change(Some(PlayerStatus::Stopped), None);
+        assert_eq!(
+            Some(PlaybackEvent::Stopped),
+            handler.classify_event(SENDER, &stopped, &numbers)
+        );
+    }
+
+    #[test]
+    fn test_handle_change_refreshes_pending_notification() {
+        let mut handler = SignalHandler::new(&Configuration::default());
+        let numbers = PlayerMetadata::default()
+            .with_track_id("/org/mpris/track/1")
+            .with_title("Numbers")
+            .with_artists(&["Kraftwerk"])
+            .with_album("Computer World");
+
+        let close = handler.handle_change(
+            SENDER.to_string(),
+            PlayerInfo::default(),
+            change(Some(PlayerStatus::Playing), Some(numbers.clone())),
+        );
+        assert!(!close);
+        assert_eq!(&numbers, handler.pending_notifications[SENDER].metadata());
+
+        // Players adding fields in a later signal aren't taken as a new
+        // track, and update the notification which is still pending.
+        let refreshed = PlayerMetadata {
+            length: Some(180_000_000),
+            ..numbers.clone()
+        };
+        let refresh = change(None, Some(refreshed.clone()));
+        assert_eq!(None, handler.classify_event(SENDER, &refresh, &refreshed));
+        handler.handle_change(SENDER.to_string(), PlayerInfo::default(), refresh);
+        assert_eq!(1, handler.pending_notifications.len());
+        assert_eq!(&refreshed, handler.pending_notifications[SENDER].metadata());
+    }
+
+    #[test]
+    fn test_handle_change_pause_and_stop() {
+        let numbers = PlayerMetadata::default()
+            .with_track_id("/org/mpris/track/1")
+            .with_title("Numbers")
+            .with_artists(&["Kraftwerk"])
+            .with_album("Computer World");
+
+        let mut handler = SignalHandler::new(&Configuration {
+            close_on_pause: true,
+            ..Configuration::default()
+        });
+        let new_track = change(None, Some(numbers.clone()));
+        handler.handle_change(SENDER.to_string(), PlayerInfo::default(), new_track);
+        let paused = change(Some(PlayerStatus::Paused), None);
+        assert!(handler.handle_change(SENDER.to_string(), PlayerInfo::default(), paused));
+        assert!(handler.pending_notifications.is_empty());
+
+        let mut handler = SignalHandler::new(&Configuration {
+            close_on_stop: false,
+            notify_on_stop: true,
+            ..Configuration::default()
+        });
+        let new_track = change(None, Some(numbers.clone()));
+        handler.handle_change(SENDER.to_string(), PlayerInfo::default(), new_track);
+        let stopped = change(Some(PlayerStatus::Stopped), None);
+        assert!(!handler.handle_change(SENDER.to_string(), PlayerInfo::default(), stopped));
+        assert_eq!(&numbers, handler.pending_notifications[SENDER].metadata());
+    }
+
+    #[test]
+    fn test_handle_change_runs_commands_without_notification() {
+        let mut handler = SignalHandler::new(&Configuration {
+            notify_on_resume: false,
+            commands: Some(vec![vec!["true".to_string()]]),
+            ..Configuration::default()
+        });
+        let numbers = PlayerMetadata::default()
+            .with_track_id("/org/mpris/track/1")
+            .with_title("Numbers")
+            .with_artists(&["Kraftwerk"])
+            .with_album("Computer World");
+        let later = || Instant::now() + NOTIFICATION_DELAY * 2;
+
+        let new_track = change(Some(PlayerStatus::Playing), Some(numbers.clone()));
+        handler.handle_change(SENDER.to_string(), PlayerInfo::default(), new_track);
+        assert!(handler.take_due_commands(Instant::now()).is_empty());
+        assert_eq!(1, handler.take_due_commands(later()).len());
+        handler.pending_notifications.clear();
+        handler
+            .notified_tracks
+            .insert(SENDER.to_string(), numbers.clone());
+
+        // Resuming isn't notified, but still runs the commands
+        let resumed = change(Some(PlayerStatus::Playing), None);
+        handler.handle_change(SENDER.to_string(), PlayerInfo::default(), resumed);
+        assert!(handler.pending_notifications.is_empty());
+        assert_eq!(1, handler.take_due_commands(later()).len());
+    }
+
+    #[test]
+    fn test_handle_change_skips_duplicates() {
+        let mut handler = SignalHandler::new(&Configuration::default());
+        let numbers = PlayerMetadata::default()
+            .with_track_id("/org/mpris/track/1")
+            .with_title("Numbers")
+            .with_artists(&["Kraftwerk"])
+            .with_album("Computer World");
+        let mirrored = PlayerMetadata {
+            track_id: Some("/org/kde/kdeconnect/1".to_string()),
+            ..numbers.clone()
+        };
+
+        // The same track from a second player is suppressed while the first
+        // player's notification is still pending.
+        let new_track = change(None, Some(numbers.clone()));
+        handler.handle_change(SENDER.to_string(), PlayerInfo::default(), new_track);
+        let mirrored_track = change(None, Some(mirrored.clone()));
+        handler.handle_change(":1.43".to_string(), PlayerInfo::default(), mirrored_track);
+        assert_eq!(1, handler.pending_notifications.len());
+        assert!(handler.pending_notifications.contains_key(SENDER));
+
+        // The skipped track is recorded as notified, so that a later signal
+        // for it isn't taken as a new track.
+        assert_eq!(Some(&mirrored), handler.notified_tracks.get(":1.43"));
+        let refresh = change(None, Some(mirrored.clone()));
+        assert_eq!(None, handler.classify_event(":1.43", &refresh, &mirrored));
+    }
+}
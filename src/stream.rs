@@ -0,0 +1,128 @@
+use crate::mpris::PlayerMetadata;
+use regex::Regex;
+
+// Internet radio players often only know the stream's ICY title, which they
+// send as `xesam:title` in the form "Artist - Song", with the station name as
+// the album and no artist. Such streams are recognized by an HTTP URL, the
+// lack of a length, and a track ID which stays the same as the title changes.
+
+const STREAM_SCHEMES: &[&str] = &["http://", "https://"];
+
+/// Whether the metadata looks like that of an internet radio stream, given
+/// the previous metadata of the same player, if any.
+pub fn is_stream(metadata: &PlayerMetadata, previous: Option<&PlayerMetadata>) -> bool {
+    let is_http = metadata
+        .track_url
+        .as_deref()
+        .is_some_and(|url| STREAM_SCHEMES.iter().any(|scheme| url.starts_with(scheme)));
+    let has_length = metadata.length.is_some_and(|length| length > 0);
+    let same_track_id = match previous {
+        Some(previous) => previous.track_id == metadata.track_id,
+        None => true,
+    };
+    is_http && !has_length && same_track_id
+}
+
+/// Sets the station of a stream from its album, where players put it.
+pub fn set_station(metadata: &mut PlayerMetadata) {
+    metadata.station = metadata.album.clone();
+}
+
+/// Splits the title of a stream into artist and title at the first match of
+/// the separator. Metadata which already names an artist is left as-is;
+/// blank artists don't count.
+pub fn split_stream_title(metadata: &mut PlayerMetadata, separator: &Regex) {
+    if metadata.has_artist() {
+        return;
+    }
+
+    let title = match metadata.title.as_deref() {
+        Some(title) => title,
+        None => return,
+    };
+    if let Some(found) = separator.find(title) {
+        let (artist, song) = (title[..found.start()].trim(), title[found.end()..].trim());
+        if !artist.is_empty() && !song.is_empty() {
+            metadata.artists = Some(vec![artist.to_string()]);
+            metadata.title = Some(song.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_stream, set_station, split_stream_title};
+    use crate::mpris::PlayerMetadata;
+    use regex::Regex;
+
+    #[test]
+    fn test_is_stream() {
+        let radio = PlayerMetadata::default()
+            .with_track_id("/org/mpv/track/0")
+            .with_album("Radio Paradise")
+            .with_url("https://stream.radioparadise.com/mp3-192");
+        let first = radio
+            .clone()
+            .with_title("Talking Heads - Once in a Lifetime");
+        let second = radio.with_title("Brian Eno - An Ending (Ascent)");
+        assert!(is_stream(&first, None));
+        assert!(is_stream(&second, Some(&first)));
+
+        let next_file = second.clone().with_track_id("/org/mpv/track/1");
+        assert!(!is_stream(&next_file, Some(&first)));
+
+        let local = first.clone().with_url("file:///music/once.flac");
+        assert!(!is_stream(&local, None));
+
+        let podcast = first.with_length(3_600_000_000);
+        assert!(!is_stream(&podcast, None));
+    }
+
+    #[test]
+    fn test_set_station() {
+        let radio = PlayerMetadata::default()
+            .with_track_id("/org/mpv/track/0")
+            .with_album("Radio Paradise")
+            .with_url("https://stream.radioparadise.com/mp3-192");
+        let mut metadata = radio.with_title("Talking Heads - Once in a Lifetime");
+        set_station(&mut metadata);
+        assert_eq!(Some("Radio Paradise".to_string()), metadata.station);
+    }
+
+    #[test]
+    fn test_split_stream_title() {
+        let separator = Regex::new(r"\s+-\s+").unwrap();
+        let radio = PlayerMetadata::default()
+            .with_track_id("/org/mpv/track/0")
+            .with_album("Radio Paradise")
+            .with_url("https://stream.radioparadise.com/mp3-192");
+        let mut metadata = radio
+            .clone()
+            .with_title("Talking Heads - Once in a Lifetime - Live");
+        split_stream_title(&mut metadata, &separator);
+        assert_eq!(Some(vec!["Talking Heads".to_string()]), metadata.artists);
+        assert_eq!(
+            Some("Once in a Lifetime - Live".to_string()),
+            metadata.title
+        );
+
+        // Titles without a separator, such as station announcements, are kept
+        let mut metadata = radio.clone().with_title("Radio Paradise");
+        split_stream_title(&mut metadata, &separator);
+        assert_eq!(None, metadata.artists);
+        assert_eq!(Some("Radio Paradise".to_string()), metadata.title);
+
+        // Blank artists are as good as none
+        let song = radio.with_title("Talking Heads - Once in a Lifetime");
+        let mut metadata = song.clone().with_artists(&[""]);
+        split_stream_title(&mut metadata, &separator);
+        assert_eq!(Some(vec!["Talking Heads".to_string()]), metadata.artists);
+
+        let mut metadata = song.with_artists(&["Radio Paradise DJ"]);
+        split_stream_title(&mut metadata, &separator);
+        assert_eq!(
+            Some(vec!["Radio Paradise DJ".to_string()]),
+            metadata.artists
+        );
+    }
+}
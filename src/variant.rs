@@ -0,0 +1,242 @@
+use rustbus::signature::{Base, Container, Type};
+use rustbus::wire::unmarshal::traits::Variant;
+use rustbus::wire::unmarshal::{UnmarshalContext, UnmarshalResult};
+use rustbus::wire::ObjectPath;
+use rustbus::{Signature, Unmarshal};
+
+// Lenient decoding of D-Bus variants. Players don't always send values with
+// the type given by the specification (e.g., a single string where an array
+// of strings is expected, or a signed integer where an unsigned one is), so
+// these functions accept the common variations of each type.
+
+// rustbus doesn't unmarshal doubles into f64, so they are read through this
+// wrapper.
+struct Double(f64);
+
+impl Signature for Double {
+    fn signature() -> Type {
+        Type::Base(Base::Double)
+    }
+
+    fn alignment() -> usize {
+        8
+    }
+}
+
+impl<'buf, 'fds> Unmarshal<'buf, 'fds> for Double {
+    fn unmarshal(ctx: &mut UnmarshalContext<'fds, 'buf>) -> UnmarshalResult<Self> {
+        let padding = ctx.align_to(Self::alignment())?;
+        let (bytes, bits) = rustbus::wire::util::parse_u64(&ctx.buf[ctx.offset..], ctx.byteorder)?;
+        ctx.offset += bytes;
+        Ok((bytes + padding, Double(f64::from_bits(bits))))
+    }
+}
+
+/// The signature of the variant's value, e.g. `as`.
+pub fn signature(variant: &Variant) -> String {
+    let mut sig = String::new();
+    variant.get_value_sig().to_str(&mut sig);
+    sig
+}
+
+/// Reads a string, also accepting object paths, numbers, and the first
+/// element of an array.
+pub fn coerce_string(variant: &Variant) -> Option<String> {
+    match variant.get_value_sig() {
+        Type::Base(Base::String) => variant.get().ok(),
+        Type::Base(Base::ObjectPath) => variant
+            .get::<ObjectPath<String>>()
+            .ok()
+            .map(|path| path.as_ref().to_string()),
+        Type::Base(Base::Boolean) => variant.get::<bool>().ok().map(|b| b.to_string()),
+        Type::Base(Base::Double) => coerce_f64(variant).map(|d| d.to_string()),
+        Type::Base(_) => coerce_i64(variant).map(|i| i.to_string()),
+        Type::Container(Container::Array(_)) => {
+            coerce_strings(variant).and_then(|strings| strings.into_iter().next())
+        }
+        Type::Container(Container::Variant) => coerce_string(&variant.get().ok()?),
+        _ => None,
+    }
+}
+
+/// Reads a list of strings, also accepting a single string, or a list of
+/// variants containing strings.
+pub fn coerce_strings(variant: &Variant) -> Option<Vec<String>> {
+    match variant.get_value_sig() {
+        Type::Container(Container::Array(element)) => match element.as_ref() {
+            Type::Base(Base::String) => variant.get().ok(),
+            Type::Base(Base::ObjectPath) => variant
+                .get::<Vec<ObjectPath<String>>>()
+                .ok()
+                .map(|paths| paths.iter().map(|p| p.as_ref().to_string()).collect()),
+            Type::Container(Container::Variant) => variant
+                .get::<Vec<Variant>>()
+                .ok()
+                .map(|variants| variants.iter().filter_map(coerce_string).collect()),
+            _ => None,
+        },
+        Type::Container(Container::Variant) => coerce_strings(&variant.get().ok()?),
+        _ => coerce_string(variant).map(|string| vec![string]),
+    }
+}
+
+/// Reads an integer of any width or signedness, also accepting doubles and
+/// numeric strings.
+pub fn coerce_i64(variant: &Variant) -> Option<i64> {
+    match variant.get_value_sig() {
+        Type::Base(Base::Byte) => variant.get::<u8>().ok().map(i64::from),
+        Type::Base(Base::Int16) => variant.get::<i16>().ok().map(i64::from),
+        Type::Base(Base::Uint16) => variant.get::<u16>().ok().map(i64::from),
+        Type::Base(Base::Int32) => variant.get::<i32>().ok().map(i64::from),
+        Type::Base(Base::Uint32) => variant.get::<u32>().ok().map(i64::from),
+        Type::Base(Base::Int64) => variant.get::<i64>().ok(),
+        Type::Base(Base::Uint64) => variant.get::<u64>().ok().and_then(|u| u.try_into().ok()),
+        Type::Base(Base::Double) => coerce_f64(variant).map(|d| d as i64),
+        Type::Base(Base::String) => variant.get::<&str>().ok()?.trim().parse().ok(),
+        Type::Container(Container::Variant) => coerce_i64(&variant.get().ok()?),
+        Type::Container(Container::Array(_)) => {
+            coerce_strings(variant)?.first()?.trim().parse().ok()
+        }
+        _ => None,
+    }
+}
+
+/// Reads a boolean, also accepting integers and "true"/"false" strings.
+pub fn coerce_bool(variant: &Variant) -> Option<bool> {
+    match variant.get_value_sig() {
+        Type::Base(Base::Boolean) => variant.get().ok(),
+        Type::Base(Base::String) => variant.get::<&str>().ok()?.trim().parse().ok(),
+        Type::Container(Container::Variant) => coerce_bool(&variant.get().ok()?),
+        _ => coerce_i64(variant).map(|i| i != 0),
+    }
+}
+
+/// Reads a double, also accepting integers and numeric strings.
+pub fn coerce_f64(variant: &Variant) -> Option<f64> {
+    match variant.get_value_sig() {
+        Type::Base(Base::Double) => variant.get::<Double>().ok().map(|d| d.0),
+        Type::Base(Base::String) => variant.get::<&str>().ok()?.trim().parse().ok(),
+        Type::Container(Container::Variant) => coerce_f64(&variant.get().ok()?),
+        _ => coerce_i64(variant).map(|i| i as f64),
+    }
+}
+
+/// Renders a value of any type as a list of strings, for display. Arrays
+/// yield one string per element, dicts one "key: value" string per entry,
+/// and nested variants are unwrapped. Structs aren't rendered.
+pub fn render(variant: &Variant) -> Option<Vec<String>> {
+    match variant.get_value_sig() {
+        Type::Base(_) => coerce_string(variant).map(|string| vec![string]),
+        Type::Container(Container::Variant) => render(&variant.get().ok()?),
+        Type::Container(Container::Array(element)) => match element.as_ref() {
+            Type::Base(Base::String) | Type::Base(Base::ObjectPath) => coerce_strings(variant),
+            Type::Base(Base::Boolean) => render_all::<bool>(variant),
+            Type::Base(Base::Byte) => render_all::<u8>(variant),
+            Type::Base(Base::Int16) => render_all::<i16>(variant),
+            Type::Base(Base::Uint16) => render_all::<u16>(variant),
+            Type::Base(Base::Int32) => render_all::<i32>(variant),
+            Type::Base(Base::Uint32) => render_all::<u32>(variant),
+            Type::Base(Base::Int64) => render_all::<i64>(variant),
+            Type::Base(Base::Uint64) => render_all::<u64>(variant),
+            Type::Base(Base::Double) => variant
+                .get::<Vec<Double>>()
+                .ok()
+                .map(|doubles| doubles.iter().map(|d| d.0.to_string()).collect()),
+            Type::Container(Container::Variant) => variant
+                .get::<Vec<Variant>>()
+                .ok()
+                .map(|variants| variants.iter().filter_map(render).flatten().collect()),
+            _ => None,
+        },
+        Type::Container(Container::Dict(Base::String, value))
+            if **value == Type::Container(Container::Variant) =>
+        {
+            let mut entries: Vec<(String, Variant)> = variant
+                .get::<std::collections::HashMap<String, Variant>>()
+                .ok()?
+                .into_iter()
+                .collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Some(
+                entries
+                    .iter()
+                    .filter_map(|(key, value)| {
+                        render(value).map(|values| format!("{key}: {}", values.join(", ")))
+                    })
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+fn render_all<'buf, 'fds, T>(variant: &Variant<'fds, 'buf>) -> Option<Vec<String>>
+where
+    T: Unmarshal<'buf, 'fds> + ToString,
+{
+    variant
+        .get::<Vec<T>>()
+        .ok()
+        .map(|values| values.iter().map(T::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        coerce_bool, coerce_f64, coerce_i64, coerce_string, coerce_strings, render, signature,
+    };
+    use rustbus::dbus_variant_sig;
+    use rustbus::message_builder::MarshalledMessageBody;
+    use rustbus::wire::unmarshal::traits::Variant;
+    use rustbus::wire::ObjectPath;
+
+    dbus_variant_sig!(NestedVariant, CaseU32 => u32);
+
+    #[test]
+    fn test_coerce_variants() {
+        let mut body = MarshalledMessageBody::new();
+        body.push_variant("Simon & Garfunkel").unwrap();
+        body.push_variant(vec!["Simon", "Garfunkel"]).unwrap();
+        body.push_variant(ObjectPath::new("/org/mpris/track/1").unwrap())
+            .unwrap();
+        body.push_variant(7_i32).unwrap();
+        body.push_variant(7_u64).unwrap();
+        body.push_variant(NestedVariant::CaseU32(3)).unwrap();
+        body.push_variant("12").unwrap();
+        body.push_variant(vec![120_u32, 128]).unwrap();
+        body.push_variant(true).unwrap();
+
+        let mut parser = body.parser();
+        let variants: Vec<Variant> = (0..9).map(|_| parser.get().unwrap()).collect();
+
+        assert_eq!("s", signature(&variants[0]));
+        assert_eq!(
+            Some(vec!["Simon & Garfunkel".to_string()]),
+            coerce_strings(&variants[0])
+        );
+        assert_eq!(Some("Simon".to_string()), coerce_string(&variants[1]));
+        assert_eq!(
+            Some("/org/mpris/track/1".to_string()),
+            coerce_string(&variants[2])
+        );
+        assert_eq!(Some(7), coerce_i64(&variants[3]));
+        assert_eq!(Some(7), coerce_i64(&variants[4]));
+        assert_eq!(Some(3), coerce_i64(&variants[5]));
+        assert_eq!(Some(12.0), coerce_f64(&variants[6]));
+        assert_eq!(None, coerce_i64(&variants[0]));
+        assert_eq!(Some(true), coerce_bool(&variants[8]));
+        assert_eq!(Some(true), coerce_bool(&variants[3]));
+        assert_eq!(None, coerce_bool(&variants[0]));
+
+        assert_eq!(
+            Some(vec!["Simon".to_string(), "Garfunkel".to_string()]),
+            render(&variants[1])
+        );
+        assert_eq!(Some(vec!["3".to_string()]), render(&variants[5]));
+        assert_eq!(
+            Some(vec!["120".to_string(), "128".to_string()]),
+            render(&variants[7])
+        );
+        assert_eq!(Some(vec!["true".to_string()]), render(&variants[8]));
+    }
+}